@@ -1,9 +1,13 @@
 use std::str::FromStr;
 use serde::{Serialize,Deserialize};
+use crate::error::{Error, Result};
+use crate::json_schema;
+use crate::models::request::JSONSchemaDefine;
+use crate::schema::JsonSchema;
 
 
 /// The options for which set of questions to propose to the PDF summary endpoints. Each variant corresponds to a JSON blob which is used as prompt
-/// Each of the variants has a corresponding struct which is used to intake the GPT response content (the structure of these structs can be autogenerated from the json strings defined in Battery's `.to_prompt( )`  )
+/// Each of the variants that asks for JSON has a corresponding struct, declared with `json_schema!`, which is used to intake the GPT response content. The JSON template in the prompt is generated from that same struct (see `.schema()`), so the two cannot drift apart.
 #[derive(Clone, Copy)]
 pub enum Battery {
     Essay, 
@@ -15,7 +19,7 @@ pub enum Battery {
 }
 
 impl FromStr for Battery {
-    type Err = String;
+    type Err = Error;
     /// Generate the corresponding enum variant from a `&str`
     /// ```
     /// impl FromStr for Battery {
@@ -32,7 +36,7 @@ impl FromStr for Battery {
             "essay" => Ok(Battery::Essay),
             "complete-voynich" => Ok(Battery::CompleteVoynich),
             "met-consensus" => Ok(Battery::MetConsensus),
-            _ => return Err("Either `from_str() for Battery` needs to be synced to Battery's variants, or you've provided an invalid battery type. You should POST to localhost:port/pdf-summary/run-battery/<battery>, where battery is one of the strings implemented in `FromStr` for `Battery`".to_string())
+            _ => Err(Error::UnknownBattery(format!("\"{s}\": either `from_str() for Battery` needs to be synced to Battery's variants, or you've provided an invalid battery type. You should POST to localhost:port/pdf-summary/run-battery/<battery>, where battery is one of the strings implemented in `FromStr` for `Battery`")))
         }
     }
 
@@ -40,23 +44,52 @@ impl FromStr for Battery {
 
 impl Battery {
     /// Convert this battery variant into a corresponding GPT prompt, that is used to ask a number of questions simultaneously about the PDF.
-    pub fn to_prompt(&self, doc: String) -> String {
+    /// # Errors
+    /// `Error::Io` if the battery's text file cannot be read
+    pub fn to_prompt(&self, doc: String) -> Result<String> {
 
-        match self {
+        let read_battery = |path: &str| std::fs::read_to_string(path).map_err(Error::io(path));
+        Ok(match self {
             Battery::Essay => {
-                let battery_text = std::fs::read_to_string("./openai_for_rs/src/models/batteries/Essay.txt").expect("reading battery from its text file");
-                format!("{battery_text} \n\n {doc}")
+                let battery_text = read_battery("./openai_for_rs/src/models/batteries/Essay.txt")?;
+                format!("{battery_text}\n{template} \n\n {doc}", template = EssayResponse::template())
             },
             Battery::CompleteVoynich => {
-                let battery_text = std::fs::read_to_string("./openai_for_rs/src/models/batteries/CompleteVoynich.txt").expect("reading battery from its text file");
-                format!("{battery_text} \n\n {doc}")
+                let battery_text = read_battery("./openai_for_rs/src/models/batteries/CompleteVoynich.txt")?;
+                format!("{battery_text}\n{template} \n\n {doc}", template = VoynichResponse::template())
             },
             Battery::MetConsensus => {
-                let battery_text = std::fs::read_to_string("./openai_for_rs/src/models/batteries/MetConsensus.txt").expect("reading battery from its text file");
+                let battery_text = read_battery("./openai_for_rs/src/models/batteries/MetConsensus.txt")?;
                 format!("{battery_text} \n\n {input}", input = doc)
             }
-        }.to_string()
+        })
+
+    }
+
+    /// The JSON schema of the response this battery asks for, or `None` for batteries answering in prose
+    pub fn schema(&self) -> Option<JSONSchemaDefine> {
+        match self {
+            Battery::Essay => Some(EssayResponse::schema()),
+            Battery::CompleteVoynich => Some(VoynichResponse::schema()),
+            Battery::MetConsensus => None,
+        }
+    }
 
+    /// Deserialize the response content of a Query made with this battery into its response struct. <br>
+    /// Anything around the outermost `{ }`, such as a markdown code fence, is ignored.
+    /// # Errors
+    /// `Error::Json` if the content does not match the battery's response struct
+    pub fn parse(&self, content: &str) -> Result<BatteryResponse> {
+        let json = match (content.find('{'), content.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &content[start..=end],
+            _ => content,
+        };
+        let context = || format!("the response to the {}", self.as_prompt_stamp());
+        Ok(match self {
+            Battery::Essay => BatteryResponse::Essay(serde_json::from_str(json).map_err(Error::json(context()))?),
+            Battery::CompleteVoynich => BatteryResponse::CompleteVoynich(Box::new(serde_json::from_str(json).map_err(Error::json(context()))?)),
+            Battery::MetConsensus => BatteryResponse::MetConsensus(content.to_string()),
+        })
     }
 
     /// Convert this battery variant into a corresponding string label, which is paired with file title to make a cache key for PdfSummaries. This allows us to not put entire documents or prompts inside the cache, but rather tether them by filename, keeping the cache readable, and allowing indefinitely large prompts,
@@ -106,61 +139,83 @@ impl Battery {
     Battery::MetConsensus => { "Consensus Meta-Battery" }, // The stamp must contain "Meta-Battery" to be read from database properly
     }.to_string()
     }
-    
-    
-    // pub fn deserialize_response(&self, json: String) 
-    // -> BatteryResponse<
-    // MinimalResponse, BasicResponse, ComprehensiveResponse, VoynichResponse, PsychReviewQualityResponse
-    // > {
-    //     use BatteryResponse::*;
-    //     use serde_json::from_str;
-    //     let json = json.as_str();
-    //     match self {
-    //         Battery::CompleteVoynich => CompleteVoynich(from_str::<VoynichResponse>(json).unwrap()),
-    //     }
-    // }
 
+    /// The battery whose `.as_prompt_stamp()` is `stamp`, as found in the `prompt` of a battery Query
+    pub fn from_prompt_stamp(stamp: &str) -> Option<Battery> {
+        [Battery::Essay, Battery::CompleteVoynich, Battery::MetConsensus].into_iter().find(|battery| battery.as_prompt_stamp() == stamp)
+    }
 
 }
 
-pub enum BatteryResponse<
-    A, B, C, D, E
-> {
-    Minimal(A),
-    Basic(B),
-    Comprehensive(C),
-    CompleteVoynich(D),
-    PsychReviewQuality(E),
+/// A battery's response content, deserialized by `Battery::parse()`
+pub enum BatteryResponse {
+    Essay(EssayResponse),
+    CompleteVoynich(Box<VoynichResponse>),
+    MetConsensus(String),
 }
 
 
-#[derive(Serialize,Deserialize)]
-pub struct MinimalResponse {
-    r#abstract: String,
-    publication_date: String,
+json_schema! {
+    pub struct EssayResponse {
+        /// article title
+        pub title: String,
+        /// Taking a skeptical perspective on the rigor of the article, use many quotes from the article to produce a detailed 4 paragraph essay on the approach, methods, results, conclusions, and assumptions.
+        pub summary: String,
+    }
 }
 
+json_schema! {
+    pub struct VoynichResponse {
+        pub title: String,
+        pub journal: String,
+        /// MM/DD/YYYY
+        pub publication_date: String,
+        pub authors: Vec<String>,
+        /// String containing quotes summarizing the methods OR fallback
+        pub methods: String,
+        /// Generate summary of assumptions
+        pub assumptions: String,
+        /// Summarize the results if present OR fallback
+        pub results: String,
+        /// Summarize the conclusions if present OR fallback
+        pub conclusions: String,
+        /// Summarize the recommendations for future research if present OR fallback
+        pub further_research: String,
+        pub keywords: Vec<String>,
+        /// Always answer 'no extras'
+        pub extra: String,
+    }
+}
 
-#[derive(Serialize,Deserialize)]
-pub struct BasicResponse {
-    title: String,
-    authors: Vec<String>,
-    methods: String,
-    results: String,
-    conclusions: String,
-    further_research: String,
-    keywords: Vec<String>,
+json_schema! {
+    pub struct MinimalResponse {
+        pub r#abstract: String,
+        pub publication_date: String,
+    }
 }
 
-#[derive(Serialize,Deserialize)]
-pub struct ComprehensiveResponse {}
+json_schema! {
+    pub struct BasicResponse {
+        pub title: String,
+        pub authors: Vec<String>,
+        pub methods: String,
+        pub results: String,
+        pub conclusions: String,
+        pub further_research: String,
+        pub keywords: Vec<String>,
+    }
+}
 
 #[derive(Serialize,Deserialize)]
-pub struct VoynichResponse {}
+pub struct ComprehensiveResponse {}
 
-#[derive(Serialize,Deserialize)]
-pub struct PsychReviewQualityResponse {
-    hypothesis_presence: f32,
-    mechanism_presence: f32,
-    balance: f32
-}
\ No newline at end of file
+json_schema! {
+    pub struct PsychReviewQualityResponse {
+        /// degree to which the paper concerns itself with hypotheses, 0.0-1.0
+        pub hypothesis_presence: f32,
+        /// degree to which the paper concerns itself with mechanisms of action, 0.0-1.0
+        pub mechanism_presence: f32,
+        /// degree to which the paper gives representing opposing perspectives equal effort, 0.0-1.0
+        pub balance: f32,
+    }
+}
@@ -0,0 +1,260 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::cache::{JsonFileCache, MemoryCache, QueryCacheBackend};
+use crate::client::{OpenAIAccount, BILL_FILEPATH, CACHE_FILEPATH, HISTORY_FILEPATH, LEDGER_FILEPATH, PRICING_FILEPATH};
+use crate::history::QueryHistory;
+use crate::ledger::{self, Ledger};
+use crate::flight::InFlight;
+use crate::spending::Spending;
+use crate::error::{Error, Result};
+use crate::models::retry::RetryPolicy;
+use crate::models::{ApiConfig, Bill, CachePolicy, GptModel, HttpConfig, Budgets, Mode, PricingTable, Tags};
+use crate::rate_limit::{RateLimiter, RateLimits};
+
+
+/// Builds an `OpenAIAccount` from explicit settings. Unlike `OpenAIAccount::new()`, nothing is read from the environment and no file is touched unless asked for, so several accounts can run side by side (or in a test) without sharing a bill, cache or query history.
+/// <br> Without `ledger_file`, `cache_file` (or `cache_backend`) and `history_file`, the bill and cache live in memory only and overwritten queries are discarded. Existing files are read on `.build()`, and missing ones are created; the query history is only ever appended to.
+/// ```
+/// # use rust_openai::{OpenAIAccount, GptModel};
+/// let account = OpenAIAccount::builder("sk-...")
+///     .model(GptModel::Gpt4)
+///     .temperature(0.2)
+///     .build()
+///     .unwrap();
+/// # futures::executor::block_on(async {
+/// assert_eq!(account.cache().len().await.unwrap(), 0);
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct OpenAIAccountBuilder {
+    api_key: String,
+    model: GptModel,
+    temperature: f32,
+    api: ApiConfig,
+    retry: RetryPolicy,
+    rate_limits: RateLimits,
+    http: HttpConfig,
+    ledger_path: Option<PathBuf>,
+    bill_path: Option<PathBuf>,
+    cache_path: Option<PathBuf>,
+    cache_backend: Option<Arc<dyn QueryCacheBackend>>,
+    cache_policy: CachePolicy,
+    history_path: Option<PathBuf>,
+    mode: Mode,
+    pricing: PricingTable,
+    pricing_path: Option<PathBuf>,
+    tags: Tags,
+    max_tokens: Option<u32>,
+    budgets: Option<Budgets>,
+}
+
+impl OpenAIAccountBuilder {
+
+    pub fn new(api_key: impl Into<String>) -> OpenAIAccountBuilder {
+        OpenAIAccountBuilder {
+            api_key: api_key.into(),
+            model: GptModel::Gpt35Turbo16k,
+            temperature: 0.0,
+            api: ApiConfig::default(),
+            retry: RetryPolicy::default(),
+            rate_limits: RateLimits::default(),
+            http: HttpConfig::default(),
+            ledger_path: None,
+            bill_path: None,
+            cache_path: None,
+            cache_backend: None,
+            cache_policy: CachePolicy::default(),
+            history_path: None,
+            mode: Mode::default(),
+            pricing: PricingTable::default(),
+            pricing_path: None,
+            tags: Tags::default(),
+            max_tokens: None,
+            budgets: None,
+        }
+    }
+
+    /// A builder using the api key in `CHATGPT_API_KEY`
+    /// # Errors
+    /// `Error::Env` if `CHATGPT_API_KEY` is not set
+    pub fn from_env() -> Result<OpenAIAccountBuilder> {
+        let api_key = env::var("CHATGPT_API_KEY").map_err(|_| Error::Env("CHATGPT_API_KEY".to_string()))?;
+        Ok(OpenAIAccountBuilder::new(api_key))
+    }
+
+    pub fn model(mut self, model: GptModel) -> OpenAIAccountBuilder {
+        self.model = model;
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> OpenAIAccountBuilder {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn api_config(mut self, api: ApiConfig) -> OpenAIAccountBuilder {
+        self.api = api;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> OpenAIAccountBuilder {
+        self.retry = retry;
+        self
+    }
+
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> OpenAIAccountBuilder {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn http_config(mut self, http: HttpConfig) -> OpenAIAccountBuilder {
+        self.http = http;
+        self
+    }
+
+    /// Record every billable event in a `Ledger` at `path`, and compute the bill from it
+    pub fn ledger_file(mut self, path: impl Into<PathBuf>) -> OpenAIAccountBuilder {
+        self.ledger_path = Some(path.into());
+        self
+    }
+
+    /// Carry the totals of a bill file written before the ledger was kept into the `ledger_file` on `.build()`, once. Without a ledger, the bill starts from them and is kept in memory
+    pub fn bill_file(mut self, path: impl Into<PathBuf>) -> OpenAIAccountBuilder {
+        self.bill_path = Some(path.into());
+        self
+    }
+
+    /// Keep the cache in a `JsonFileCache` at `path`, opened on `.build()`. Replaces any `cache_backend`
+    pub fn cache_file(mut self, path: impl Into<PathBuf>) -> OpenAIAccountBuilder {
+        self.cache_path = Some(path.into());
+        self.cache_backend = None;
+        self
+    }
+
+    /// Keep the cache in `backend`, e.g. a `SqliteCache` or a `SeaOrmCache` over the server's database. Replaces any `cache_file`
+    /// <br> Pass clones of the same `Arc` to share one backend between several accounts.
+    pub fn cache_backend(mut self, backend: Arc<dyn QueryCacheBackend>) -> OpenAIAccountBuilder {
+        self.cache_backend = Some(backend);
+        self.cache_path = None;
+        self
+    }
+
+    /// TTLs and size limits to keep the cache to. The default keeps every entry forever
+    pub fn cache_policy(mut self, policy: CachePolicy) -> OpenAIAccountBuilder {
+        self.cache_policy = policy;
+        self
+    }
+
+    /// Keep overwritten, evicted and replaced queries as versions in a `QueryHistory` at `path`
+    pub fn history_file(mut self, path: impl Into<PathBuf>) -> OpenAIAccountBuilder {
+        self.history_path = Some(path.into());
+        self
+    }
+
+    /// Ask OpenAI, record, replay or stay offline, see `Mode`. The default asks OpenAI
+    pub fn mode(mut self, mode: Mode) -> OpenAIAccountBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Price Queries with `pricing` instead of the built-in `PricingTable`. Replaces any `pricing_file`
+    pub fn pricing(mut self, pricing: PricingTable) -> OpenAIAccountBuilder {
+        self.pricing = pricing;
+        self.pricing_path = None;
+        self
+    }
+
+    /// Price Queries with the built-in `PricingTable` plus the prices in the file at `path`, read on `.build()`. Replaces any `pricing`
+    pub fn pricing_file(mut self, path: impl Into<PathBuf>) -> OpenAIAccountBuilder {
+        self.pricing_path = Some(path.into());
+        self
+    }
+
+    /// Attribute every completion of the account to `tags`, see `OpenAIAccount::with_tags()`
+    pub fn tags(mut self, tags: Tags) -> OpenAIAccountBuilder {
+        self.tags = tags;
+        self
+    }
+
+    /// Cap every completion at `max_tokens`, see `OpenAIAccount::set_max_tokens()`. Also what `Budgets` count each request as costing at most, instead of a completion filling the context window
+    pub fn max_tokens(mut self, max_tokens: u32) -> OpenAIAccountBuilder {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Refuse requests that could take a user, library or job past its limit in `budgets`, see `Budgets`. Needs a `ledger_file`, which spending is read from
+    /// ```
+    /// # use rust_openai::models::{Budgets, Membership, Tags};
+    /// # use rust_openai::{Error, GptModel, OpenAIAccount};
+    /// let ledger = std::env::temp_dir().join("budgets-doctest.jsonl");
+    /// let account = OpenAIAccount::builder("sk-...").ledger_file(&ledger).budgets(Budgets::default()).build().unwrap();
+    /// # futures::executor::block_on(async {
+    /// // A request attributed to no one is refused, rather than let through unchecked
+    /// let untagged = account.get_completion("Spell alphabet".to_string(), Some(GptModel::Gpt4)).await;
+    /// assert!(matches!(untagged, Err(Error::Untagged { .. })));
+    /// // Uncapped, gpt-4-32k may cost more than an Unpaid user's $1 a month, even with nothing spent yet
+    /// let unpaid = account.with_tags(Tags::new().user("42").membership(Membership::Unpaid));
+    /// let refused = unpaid.get_completion("Spell alphabet".to_string(), Some(GptModel::Gpt432k)).await;
+    /// assert!(matches!(refused, Err(Error::BudgetExceeded { .. })));
+    /// # });
+    /// ```
+    pub fn budgets(mut self, budgets: Budgets) -> OpenAIAccountBuilder {
+        self.budgets = Some(budgets);
+        self
+    }
+
+    /// Keep the ledger, cache and query history in LEDGER_FILEPATH, CACHE_FILEPATH and HISTORY_FILEPATH in the working directory, carry the bill in BILL_FILEPATH into the ledger, and read prices from PRICING_FILEPATH if there is one, as `OpenAIAccount::new()` does
+    pub fn default_files(self) -> OpenAIAccountBuilder {
+        self.ledger_file(LEDGER_FILEPATH).bill_file(BILL_FILEPATH).cache_file(CACHE_FILEPATH).history_file(HISTORY_FILEPATH).pricing_file(PRICING_FILEPATH)
+    }
+
+    /// # Errors
+    /// `Error::Io` if the ledger, bill or cache file cannot be read or created or the pricing file cannot be read, `Error::Corrupt` if the ledger, bill or cache cannot be parsed, `Error::Json` if the pricing file cannot, `Error::Usage` if there are `budgets` but no ledger, and the errors of `HttpConfig::client()`
+    pub fn build(self) -> Result<OpenAIAccount> {
+        if self.budgets.is_some() && self.ledger_path.is_none() { return Err(Error::Usage("budgets need a ledger_file to read spending from".to_string())) }
+        let ledger = self.ledger_path.map(Ledger::new);
+        let bill = match (&ledger, &self.bill_path) {
+            (Some(ledger), bill_path) => {
+                if let Some(path) = bill_path { ledger.import_bill(path)?; }
+                let bill = ledger.bill()?;
+                println!("🧾 Bill read from the ledger at: {}", ledger.path().display());
+                bill
+            },
+            (None, Some(path)) => ledger::read_bill(path)?.unwrap_or_default(),
+            (None, None) => Bill::default(),
+        };
+        let cache: Arc<dyn QueryCacheBackend> = match (self.cache_backend, &self.cache_path) {
+            (Some(backend), _) => backend,
+            (None, Some(path)) => Arc::new(JsonFileCache::open(path)?),
+            (None, None) => Arc::new(MemoryCache::new()),
+        };
+        let client = self.http.client()?;
+        let pricing = match &self.pricing_path { Some(path) => PricingTable::load(path)?, None => self.pricing };
+
+        println!("🌡️  Model initialized at temperature {}", self.temperature);
+        Ok(OpenAIAccount {
+            model: self.model,
+            api_key: self.api_key,
+            api: self.api,
+            retry: self.retry,
+            rate_limiter: RateLimiter::new(self.rate_limits),
+            http: self.http,
+            client,
+            temperature: self.temperature,
+            bill: Arc::new(Mutex::new(bill)),
+            cache,
+            cache_policy: self.cache_policy,
+            ledger,
+            history: self.history_path.map(QueryHistory::new),
+            in_flight: InFlight::default(),
+            spending: Spending::default(),
+            mode: self.mode,
+            pricing,
+            tags: self.tags,
+            max_tokens: self.max_tokens,
+            budgets: self.budgets,
+        })
+    }
+}
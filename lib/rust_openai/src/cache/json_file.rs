@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+
+use super::QueryCacheBackend;
+use crate::error::{Error, Result};
+use crate::models::Query;
+use crate::persist::{sibling, write_json_atomic, FileLock};
+
+
+/// Journal entries written before a compaction, unless set with `.with_compaction_threshold()`
+pub const DEFAULT_COMPACTION_THRESHOLD: usize = 1000;
+
+/// A map kept in memory and persisted as a JSON snapshot plus an append-only journal. This is the `cache.json` of `OpenAIAccount::new()`. <br>
+/// - Every change is appended to `{path}.journal` as one JSON line and flushed to disk, so caching a Query costs one line however big the cache is.
+/// - Once the journal holds `compaction_threshold` entries, the map is written to `{path}.tmp` and renamed over the snapshot at `path`, the previous snapshot having been copied to `{path}.bak`, and the journal is emptied.
+///
+/// On `open`, the snapshot is read and the journal replayed over it. A journal whose last line was cut short by a crash loses that line only. A snapshot that cannot be parsed is never replaced by an empty cache: the `.bak` snapshot is loaded instead, or opening fails with `Error::Corrupt`.
+///
+/// Several processes can open the same cache, e.g. a server and a batch script in one working directory. Each takes an advisory lock on `{path}.lock` — shared to read, exclusive to write — and first catches up with what the others wrote: new journal lines are replayed, and a snapshot compacted by another process is read again. Hits recorded with `touch` are journaled too, so they are shared the same way.
+#[derive(Debug)]
+pub struct JsonFileCache {
+    path: PathBuf,
+    compaction_threshold: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    map: HashMap<String, Query>,
+    journal: fs::File,
+    /// Entries in the journal since the last compaction
+    journaled: usize,
+    /// Bytes of the journal already applied to `map`
+    offset: u64,
+    /// The snapshot as it was when `map` was read from it or written to it
+    snapshot: Option<Stamp>,
+}
+
+/// Modification time and length of a file, which tell whether another process replaced it
+type Stamp = (SystemTime, u64);
+
+/// One line of the journal
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum JournalEntry {
+    Put { key: String, query: Box<Query> },
+    Remove { key: String },
+    Clear,
+    /// A hit on the entry at `key`, setting its `last_used`
+    Touch { key: String, at: i64 },
+}
+
+impl JsonFileCache {
+
+    /// Read the cache at `path` and replay its journal, creating an empty cache file if there is none.
+    /// # Errors
+    /// `Error::Corrupt` if neither the snapshot nor its `.bak` can be parsed, or a journal line other than the last cannot be parsed, and `Error::Io` if the files cannot be read or created
+    pub fn open(path: impl Into<PathBuf>) -> Result<JsonFileCache> {
+        let path = path.into();
+        let backup = sibling(&path, "bak");
+        let _lock = FileLock::exclusive(&path)?;
+
+        let mut map = match read_snapshot(&path) {
+            Ok(Some(map)) => {
+                println!("🗳️  Cache read from: {}", path.display());
+                map
+            },
+            Ok(None) if backup.exists() => {
+                println!("🗳️  Cache missing at {}, reading its backup instead", path.display());
+                read_snapshot(&backup)?.unwrap_or_default()
+            },
+            Ok(None) => {
+                write_json_atomic(&path, &HashMap::<String, Query>::new(), "an empty cache")?;
+                println!("🗳️  Empty Cache created at: {}", path.display());
+                HashMap::new()
+            },
+            Err(corrupt) => match read_snapshot(&backup) {
+                Ok(Some(map)) => {
+                    let aside = sibling(&path, &format!("corrupt-{}", chrono::Utc::now().timestamp()));
+                    fs::rename(&path, &aside).map_err(Error::io(&path))?;
+                    println!("❌ {corrupt}");
+                    println!("🗳️  Cache read from its backup {} instead. The corrupt file was moved to {}", backup.display(), aside.display());
+                    map
+                },
+                _ => return Err(corrupt),
+            },
+        };
+
+        let journal_path = sibling(&path, "journal");
+        let (journaled, offset) = replay_journal(&journal_path, &mut map, 0, true)?;
+        let journal = fs::OpenOptions::new().create(true).append(true).open(&journal_path).map_err(Error::io(&journal_path))?;
+        if journaled > 0 { println!("🗳️  Replayed {journaled} journal entries from: {}", journal_path.display()); }
+
+        let state = State { map, journal, journaled, offset, snapshot: stamp(&path) };
+        Ok(JsonFileCache { path, compaction_threshold: DEFAULT_COMPACTION_THRESHOLD, state: Mutex::new(state) })
+    }
+
+    /// Compact once the journal holds `threshold` entries, instead of `DEFAULT_COMPACTION_THRESHOLD`
+    pub fn with_compaction_threshold(mut self, threshold: usize) -> JsonFileCache {
+        self.compaction_threshold = threshold.max(1);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the whole map as a new snapshot and empty the journal, see `JsonFileCache`. Also records the hits made since the last compaction
+    pub fn compact(&self) -> Result<()> {
+        let (mut state, _lock) = self.write_state()?;
+        self.compact_locked(&mut state)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The state, caught up with what other processes wrote, under the shared lock
+    fn read_state(&self) -> Result<MutexGuard<'_, State>> {
+        let mut state = self.state();
+        let _lock = FileLock::shared(&self.path)?;
+        self.refresh(&mut state, false)?;
+        Ok(state)
+    }
+
+    /// The state, caught up with what other processes wrote, with the exclusive lock held until the returned `FileLock` is dropped
+    fn write_state(&self) -> Result<(MutexGuard<'_, State>, FileLock)> {
+        let mut state = self.state();
+        let lock = FileLock::exclusive(&self.path)?;
+        self.refresh(&mut state, true)?;
+        Ok((state, lock))
+    }
+
+    /// Replay the journal lines appended by other processes, or read the whole cache again if another process compacted it. Only a writer (`repair`) fixes a journal left torn by a crash
+    fn refresh(&self, state: &mut State, repair: bool) -> Result<()> {
+        let journal_path = sibling(&self.path, "journal");
+        let journal_len = fs::metadata(&journal_path).map_or(0, |metadata| metadata.len());
+        let snapshot = stamp(&self.path);
+
+        if snapshot != state.snapshot || journal_len < state.offset {
+            let mut map = read_snapshot(&self.path)?.unwrap_or_default();
+            let (journaled, offset) = replay_journal(&journal_path, &mut map, 0, repair)?;
+            println!("🗳️  Cache read again from {}, compacted by another process", self.path.display());
+            state.map = map;
+            state.journaled = journaled;
+            state.offset = offset;
+            state.snapshot = snapshot;
+        } else if journal_len > state.offset {
+            let (journaled, offset) = replay_journal(&journal_path, &mut state.map, state.offset, repair)?;
+            state.journaled += journaled;
+            state.offset = offset;
+        }
+        Ok(())
+    }
+
+    fn compact_locked(&self, state: &mut State) -> Result<()> {
+        if self.path.exists() {
+            let backup = sibling(&self.path, "bak");
+            fs::copy(&self.path, &backup).map_err(Error::io(&backup))?;
+        }
+        write_json_atomic(&self.path, &state.map, "the cache")?;
+        let journal_path = sibling(&self.path, "journal");
+        state.journal.set_len(0).map_err(Error::io(&journal_path))?;
+        state.journaled = 0;
+        state.offset = 0;
+        state.snapshot = stamp(&self.path);
+        Ok(())
+    }
+
+    /// Append `entry` to the journal and flush it to disk. The caller holds the state from `.write_state()`, applies the entry to the map, then calls `.compact_if_full()`
+    fn append(&self, state: &mut State, entry: &JournalEntry) -> Result<()> {
+        let journal_path = sibling(&self.path, "journal");
+        let mut line = serde_json::to_vec(entry).map_err(Error::json("a cache journal entry"))?;
+        line.push(b'\n');
+        state.journal.write_all(&line).map_err(Error::io(&journal_path))?;
+        state.journal.sync_data().map_err(Error::io(&journal_path))?;
+        state.journaled += 1;
+        state.offset += line.len() as u64;
+        Ok(())
+    }
+
+    fn compact_if_full(&self, state: &mut State) -> Result<()> {
+        if state.journaled >= self.compaction_threshold { self.compact_locked(state)? }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueryCacheBackend for JsonFileCache {
+    async fn get(&self, key: &str) -> Result<Option<Query>> {
+        Ok(self.read_state()?.map.get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, query: &Query) -> Result<Option<Query>> {
+        let (mut state, _lock) = self.write_state()?;
+        self.append(&mut state, &JournalEntry::Put { key: key.to_string(), query: Box::new(query.clone()) })?;
+        let replaced = state.map.insert(key.to_string(), query.clone());
+        self.compact_if_full(&mut state)?;
+        Ok(replaced)
+    }
+
+    async fn remove(&self, key: &str) -> Result<Option<Query>> {
+        let (mut state, _lock) = self.write_state()?;
+        if !state.map.contains_key(key) { return Ok(None) }
+        self.append(&mut state, &JournalEntry::Remove { key: key.to_string() })?;
+        let removed = state.map.remove(key);
+        self.compact_if_full(&mut state)?;
+        Ok(removed)
+    }
+
+    async fn remove_many(&self, keys: &[String]) -> Result<Vec<Query>> {
+        let (mut state, _lock) = self.write_state()?;
+        let mut removed = Vec::new();
+        for key in keys {
+            if !state.map.contains_key(key) { continue }
+            self.append(&mut state, &JournalEntry::Remove { key: key.clone() })?;
+            removed.extend(state.map.remove(key));
+        }
+        self.compact_if_full(&mut state)?;
+        Ok(removed)
+    }
+
+    /// Hits are journaled like any change, so `last_used` survives a restart and reaches the other processes sharing the cache, for `Eviction::LeastRecentlyUsed`
+    async fn touch(&self, key: &str, at: i64) -> Result<()> {
+        let (mut state, _lock) = self.write_state()?;
+        if !state.map.contains_key(key) { return Ok(()) }
+        self.append(&mut state, &JournalEntry::Touch { key: key.to_string(), at })?;
+        if let Some(query) = state.map.get_mut(key) { query.last_used = Some(at) }
+        self.compact_if_full(&mut state)
+    }
+
+    async fn iter(&self) -> Result<Vec<(String, Query)>> {
+        Ok(self.read_state()?.map.iter().map(|(key, query)| (key.clone(), query.clone())).collect())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        let (mut state, _lock) = self.write_state()?;
+        self.append(&mut state, &JournalEntry::Clear)?;
+        state.map.clear();
+        self.compact_if_full(&mut state)?;
+        println!("🗳️  Cache cleared at: {}", self.path.display());
+        Ok(())
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.read_state()?.map.len())
+    }
+}
+
+
+/// The map in the snapshot at `path`, `None` if there is no such file. An empty file is an empty cache
+fn read_snapshot(path: &Path) -> Result<Option<HashMap<String, Query>>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io { path: path.to_path_buf(), source: e }),
+    };
+    if text.trim().is_empty() { return Ok(Some(HashMap::new())) }
+    serde_json::from_str(&text).map(Some).map_err(|e| Error::Corrupt { path: path.to_path_buf(), message: e.to_string() })
+}
+
+/// Apply the entries of the journal at `path` from byte `from` on to `map`, returning how many there were and the byte up to which the journal was applied. <br>
+/// A last line without its newline may be cut short by a crash: with `repair`, it is dropped from the journal, or completed if it parses, and otherwise it is left for a writer to repair. Without `repair`, the journal is never written
+fn replay_journal(path: &Path, map: &mut HashMap<String, Query>, from: u64, repair: bool) -> Result<(usize, u64)> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, from)),
+        Err(e) => return Err(Error::Io { path: path.to_path_buf(), source: e }),
+    };
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(from)).and_then(|_| file.read_to_end(&mut bytes)).map_err(Error::io(path))?;
+    let text = String::from_utf8_lossy(&bytes);
+
+    let mut replayed = 0;
+    let mut valid_len = 0;
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    for (number, line) in lines.iter().enumerate() {
+        if !line.ends_with('\n') && !repair { break }
+        if line.trim().is_empty() { valid_len += line.len(); continue }
+        match serde_json::from_str::<JournalEntry>(line) {
+            Ok(JournalEntry::Put { key, query }) => { map.insert(key, *query); },
+            Ok(JournalEntry::Remove { key }) => { map.remove(&key); },
+            Ok(JournalEntry::Clear) => map.clear(),
+            Ok(JournalEntry::Touch { key, at }) => { if let Some(query) = map.get_mut(&key) { query.last_used = Some(at) } },
+            // Only a line without its newline can be torn by a crash. A complete line that does not parse is real data gone bad, and is never dropped
+            Err(e) if repair && number + 1 == lines.len() && !line.ends_with('\n') => {
+                println!("🗳️  Dropping the last journal entry, cut short by a crash: {e}");
+                let journal = fs::OpenOptions::new().write(true).open(path).map_err(Error::io(path))?;
+                journal.set_len(from + valid_len as u64).map_err(Error::io(path))?;
+                break
+            },
+            Err(e) => return Err(Error::Corrupt { path: path.to_path_buf(), message: format!("entry at byte {}: {e}", from + valid_len as u64) }),
+        }
+        valid_len += line.len();
+        replayed += 1;
+    }
+    // A complete last entry missing its newline would run into the next one
+    if repair && valid_len == text.len() && !text.is_empty() && !text.ends_with('\n') {
+        let mut journal = fs::OpenOptions::new().append(true).open(path).map_err(Error::io(path))?;
+        journal.write_all(b"\n").map_err(Error::io(path))?;
+        valid_len += 1;
+    }
+    Ok((replayed, from + valid_len as u64))
+}
+
+/// The `Stamp` of the file at `path`, `None` if there is no such file
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::error::{Error, Result};
+use crate::models::hash::calculate_hash;
+use crate::models::request::ChatCompletionRequest;
+
+
+/// The cache key of `req`: a readable `label` followed by the SHA-256 of the canonical request, as `"{label} [{digest}]"`. <br>
+/// The digest covers everything that shapes the answer — model, messages, temperature, functions — so asking the same thing of another model or at another temperature is a cache miss. `stream` and `stream_options` are left out, so a streamed and a blocking completion of the same request share an entry.
+/// <br> For PDF queries, pass the `document_hash()` of the source file, and leave the document's text out of `req`: the key can then be computed without extracting the text, and still changes whenever the file does.
+/// # Errors
+/// `Error::Json` if the request cannot be serialized
+pub fn request_key(label: &str, req: &ChatCompletionRequest, document: Option<&str>) -> Result<String> {
+    Ok(format!("{label} [{}]", request_digest(req, document)?))
+}
+
+/// The SHA-256 of the canonical request, as used in `request_key()`
+/// # Errors
+/// `Error::Json` if the request cannot be serialized
+pub fn request_digest(req: &ChatCompletionRequest, document: Option<&str>) -> Result<String> {
+    let mut request = serde_json::to_value(req).map_err(Error::json("the request to a cache key"))?;
+    if let Value::Object(fields) = &mut request {
+        fields.remove("stream");
+        fields.remove("stream_options");
+    }
+    let mut fingerprint = Map::new();
+    fingerprint.insert("request".to_string(), request);
+    if let Some(document) = document {
+        fingerprint.insert("document".to_string(), Value::String(document.to_string()));
+    }
+
+    let canonical = canonicalize(Value::Object(fingerprint)).to_string();
+    Ok(calculate_hash(canonical))
+}
+
+/// The SHA-256 of the file at `path`, in hex
+/// # Errors
+/// `Error::Io` if the file cannot be read
+pub fn document_hash(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(Error::io(path))?;
+    Ok(calculate_hash(bytes))
+}
+
+/// The readable part of a key made by `request_key()`, or the whole key if it predates request keys
+pub fn label(key: &str) -> &str {
+    digest(key).map_or(key, |digest| &key[..key.len() - digest.len() - 3])
+}
+
+/// Whether `key` was made by `request_key()`, as opposed to a key from before cache keys covered the whole request
+pub fn is_request_key(key: &str) -> bool {
+    digest(key).is_some()
+}
+
+/// The hex digest in the `" [{digest}]"` suffix of `key`, if it has one
+fn digest(key: &str) -> Option<&str> {
+    let digest = key.strip_suffix(']')?.rsplit_once(" [")?.1;
+    (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())).then_some(digest)
+}
+
+/// Sort the keys of every object, so that the serialization no longer depends on field or insertion order
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let mut fields: Vec<(String, Value)> = fields.into_iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(fields.into_iter().map(|(key, value)| (key, canonicalize(value))).collect())
+        },
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::QueryCacheBackend;
+use crate::error::Result;
+use crate::models::Query;
+
+
+/// A cache that lives only as long as the account, for tests and one-off jobs. The default of `OpenAIAccountBuilder`.
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+    map: Mutex<HashMap<String, Query>>,
+}
+
+impl MemoryCache {
+
+    pub fn new() -> MemoryCache {
+        MemoryCache::default()
+    }
+
+    /// A cache starting out with `map`'s entries
+    pub fn from_map(map: HashMap<String, Query>) -> MemoryCache {
+        MemoryCache { map: Mutex::new(map) }
+    }
+
+    fn map(&self) -> std::sync::MutexGuard<'_, HashMap<String, Query>> {
+        self.map.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl QueryCacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<Query>> {
+        Ok(self.map().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, query: &Query) -> Result<Option<Query>> {
+        Ok(self.map().insert(key.to_string(), query.clone()))
+    }
+
+    async fn remove(&self, key: &str) -> Result<Option<Query>> {
+        Ok(self.map().remove(key))
+    }
+
+    async fn touch(&self, key: &str, at: i64) -> Result<()> {
+        if let Some(query) = self.map().get_mut(key) { query.last_used = Some(at) }
+        Ok(())
+    }
+
+    async fn iter(&self) -> Result<Vec<(String, Query)>> {
+        Ok(self.map().iter().map(|(key, query)| (key.clone(), query.clone())).collect())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.map().clear();
+        Ok(())
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.map().len())
+    }
+}
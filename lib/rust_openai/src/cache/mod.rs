@@ -0,0 +1,67 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::models::Query;
+
+pub mod memory;
+pub mod json_file;
+pub mod sea_orm;
+pub mod sqlite;
+pub mod key;
+pub mod rekey;
+pub mod stats;
+
+pub use memory::MemoryCache;
+pub use json_file::JsonFileCache;
+pub use self::sea_orm::{SeaOrmCache, RehashReport};
+pub use sqlite::SqliteCache;
+pub use rekey::{rekey, RekeyReport};
+pub use stats::CacheStats;
+
+
+/// Where an `OpenAIAccount` keeps its Queries, chosen at construction with `OpenAIAccountBuilder::cache_backend` (or `.cache_file()` for a `JsonFileCache`). <br>
+/// - `MemoryCache`: a map that lives as long as the account
+/// - `JsonFileCache`: a map persisted to a JSON snapshot plus an append-only journal, the `cache.json` of `OpenAIAccount::new()`
+/// - `SqliteCache`: a `query_cache` table in a local SQLite file
+/// - `SeaOrmCache`: the `query_cache` table of any SeaORM connection, such as the server's MySQL database
+///
+/// Keys are passed as the account builds them with `key::request_key()`, a readable label followed by the digest of the whole request (`"{title} - {battery stamp} [{sha256}]"`); backends store them as given. Caches from before request keys are migrated with `rekey()`.
+/// <br> Methods take `&self`, so a backend can be shared between accounts behind an `Arc`.
+#[async_trait]
+pub trait QueryCacheBackend: Send + Sync + Debug {
+    async fn get(&self, key: &str) -> Result<Option<Query>>;
+
+    /// Store `query` at `key`, returning the Query it replaced, if any
+    async fn put(&self, key: &str, query: &Query) -> Result<Option<Query>>;
+
+    async fn remove(&self, key: &str) -> Result<Option<Query>>;
+
+    /// Remove every key of `keys`, returning the Queries that were there. Backends that write the whole cache at once override it to write only once
+    async fn remove_many(&self, keys: &[String]) -> Result<Vec<Query>> {
+        let mut removed = Vec::new();
+        for key in keys {
+            removed.extend(self.remove(key).await?);
+        }
+        Ok(removed)
+    }
+
+    /// Record a cache hit on `key` at `at`, in unix milliseconds, as the Query's `last_used`, for LRU eviction. Backends that cannot store it keep the default, which does nothing, and are evicted by age instead
+    async fn touch(&self, _key: &str, _at: i64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Every entry of the cache, in no particular order
+    async fn iter(&self) -> Result<Vec<(String, Query)>>;
+
+    async fn clear(&self) -> Result<()>;
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.iter().await?.len())
+    }
+
+    async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+
+use super::QueryCacheBackend;
+use super::key::{document_hash, is_request_key, request_key};
+use crate::batteries::Battery;
+use crate::client::pdf_path;
+use crate::error::Result;
+use crate::models::{ChatCompletionRequest, Query, QueryType};
+
+
+/// What `rekey()` did to each entry of a cache
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RekeyReport {
+    /// `(old key, new key)` of every entry moved to a request key
+    pub rekeyed: Vec<(String, String)>,
+    /// Number of entries that already had a request key
+    pub current: usize,
+    /// `(key, reason)` of every entry left at its old key
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Move every entry of `cache` that predates request keys to the key `request_key()` gives it today, by rebuilding the request from the Query's model, temperature and prompt. <br>
+/// - Prompt completions are re-keyed from their prompt
+/// - Battery completions (`"{title} - {battery stamp}"`) and `ask_about_pdf` completions (`"{title}: {prompt}"`) need the source pdf, looked up in `pdf_dir` as the pdf methods of `OpenAIAccount` do. Those whose pdf is gone are skipped.
+/// - Meta completions and conversation turns are skipped, as their requests were built from state the Query does not keep. They stay readable at their old keys, but will never be hit again.
+///
+/// Works on any backend, so it migrates `cache.json` through a `JsonFileCache` and the `query_cache` table through a `SeaOrmCache`. Running it twice is harmless.
+/// # Errors
+/// The errors of the backend. An entry that cannot be rebuilt, e.g. because its battery's text file is missing, is skipped rather than failing the migration.
+pub async fn rekey(cache: &dyn QueryCacheBackend, pdf_dir: &str) -> Result<RekeyReport> {
+    let mut report = RekeyReport::default();
+
+    for (key, query) in cache.iter().await? {
+        if is_request_key(&key) {
+            report.current += 1;
+            continue
+        }
+        let new_key = match rebuild_key(&key, &query, pdf_dir) {
+            Ok(new_key) => new_key,
+            Err(reason) => {
+                println!("🗳️  Kept \"{key}\": {reason}");
+                report.skipped.push((key, reason));
+                continue
+            },
+        };
+        cache.remove(&key).await?;
+        cache.put(&new_key, &query).await?;
+        report.rekeyed.push((key, new_key));
+    }
+
+    println!("🗳️  Re-keyed {} cache entries, {} already current, {} kept at their old key", report.rekeyed.len(), report.current, report.skipped.len());
+    Ok(report)
+}
+
+/// The request key of a Query cached at the legacy `key`, or why it cannot be rebuilt
+fn rebuild_key(key: &str, query: &Query, pdf_dir: &str) -> std::result::Result<String, String> {
+    let pdf_hash = |title: &str| document_hash(pdf_path(Some(pdf_dir.to_string()), title)).map_err(|e| e.to_string());
+
+    match query.query_type {
+        QueryType::PromptCompletion => {
+            let req = ChatCompletionRequest::from_prompt(query.model, query.prompt.clone(), query.temperature);
+            // Prompt completion keys are lowercased, as in `OpenAIAccount::cache_query`
+            Ok(request_key(key, &req, None).map_err(|e| e.to_string())?.to_lowercase().replace('\n', " "))
+        },
+        QueryType::PdfCompletion => {
+            let battery = Battery::from_prompt_stamp(&query.prompt);
+            let battery_title = battery.and_then(|_| key.strip_suffix(&format!(" - {}", query.prompt)));
+            let question_title = key.strip_suffix(&format!(": {}", query.prompt));
+            match (battery, battery_title, question_title) {
+                (Some(battery), Some(title), _) => {
+                    let req = ChatCompletionRequest::from_prompt(query.model, battery.to_prompt(String::new()).map_err(|e| e.to_string())?, query.temperature);
+                    request_key(key, &req, Some(&pdf_hash(title)?)).map_err(|e| e.to_string())
+                },
+                (_, _, Some(title)) => {
+                    let req = ChatCompletionRequest::from_prompt(query.model, query.prompt.clone(), query.temperature);
+                    request_key(key, &req, Some(&pdf_hash(title)?)).map_err(|e| e.to_string())
+                },
+                _ => Err("the key matches neither a battery nor a question about a pdf".to_string()),
+            }
+        },
+        QueryType::MetaCompletion => Err("a meta completion's request depends on the cache it was run on".to_string()),
+        QueryType::Conversation => Err("a conversation turn's history is not kept in its Query".to_string()),
+    }
+}
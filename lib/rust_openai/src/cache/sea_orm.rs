@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait};
+use serde::Serialize;
+
+use super::QueryCacheBackend;
+use crate::error::Result;
+use crate::models::Query;
+use crate::models::db::prelude::QueryCache;
+use crate::models::db::query_cache::{ActiveModel, Column, Model};
+use crate::models::hash::calculate_hash;
+
+
+/// A cache read from and written to the `query_cache` table (see `db.sql`) of a SeaORM connection, so every Query is in the database as soon as it is made, with no `db_insert_cache` step. <br>
+/// Rows are looked up by `query_key_hash`, and the `query_key` is compared as well in case two keys share a hash. Rows hashed before `query_key_hash` was a SHA-256 are not found until `.rehash()` is run.
+#[derive(Debug, Clone)]
+pub struct SeaOrmCache {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmCache {
+
+    pub fn new(db: DatabaseConnection) -> SeaOrmCache {
+        SeaOrmCache { db }
+    }
+
+    pub fn connection(&self) -> &DatabaseConnection {
+        &self.db
+    }
+
+    /// Recompute the `query_key_hash` of every row from its `query_key`, e.g. after the hash function changed.
+    /// <br> Rows whose keys would share a hash — in practice, the same key stored twice — are collisions: they are reported and left untouched, as the column is unique. Remove the extra rows and run it again.
+    pub async fn rehash(&self) -> Result<RehashReport> {
+        println!("🗄️  Recomputing query key hashes...");
+        let mut by_hash: HashMap<String, Vec<Model>> = HashMap::new();
+        for row in QueryCache::find().all(&self.db).await? {
+            by_hash.entry(calculate_hash(&row.query_key)).or_default().push(row);
+        }
+
+        let mut report = RehashReport::default();
+        for (hash, rows) in by_hash {
+            if rows.len() > 1 {
+                println!("🗄️  Collision at query key hash {hash}: rows {:?}", rows.iter().map(|row| row.rid).collect::<Vec<_>>());
+                report.collisions.push(rows.into_iter().map(|row| (row.rid, row.query_key)).collect());
+                continue
+            }
+            let row = rows.into_iter().next().expect("grouped rows are never empty");
+            if row.query_key_hash == hash {
+                report.unchanged += 1;
+                continue
+            }
+            let mut active: ActiveModel = row.into();
+            active.query_key_hash = ActiveValue::Set(hash);
+            active.update(&self.db).await?;
+            report.rehashed += 1;
+        }
+
+        println!("🗄️  Rehashed {} rows, {} already current, {} collisions.", report.rehashed, report.unchanged, report.collisions.len());
+        Ok(report)
+    }
+
+    async fn find(&self, key: &str) -> Result<Option<Model>> {
+        find(&self.db, key).await
+    }
+}
+
+/// The row of `key`, read through `db`, which may be a transaction
+async fn find(db: &impl ConnectionTrait, key: &str) -> Result<Option<Model>> {
+    let rows = QueryCache::find().filter(Column::QueryKeyHash.eq(calculate_hash(key))).all(db).await?;
+    Ok(rows.into_iter().find(|row| row.query_key == key))
+}
+
+#[async_trait]
+impl QueryCacheBackend for SeaOrmCache {
+    async fn get(&self, key: &str) -> Result<Option<Query>> {
+        self.find(key).await?.map(|row| row.to_query()).transpose()
+    }
+
+    /// Replaces the row of `key` in one transaction, so a failed insert keeps the old row. Of two concurrent puts of a new key, the second fails on the unique `query_key_hash` rather than adding a duplicate row
+    async fn put(&self, key: &str, query: &Query) -> Result<Option<Query>> {
+        let row = ActiveModel::from_query(key, query)?;
+        let txn = self.db.begin().await?;
+        let replaced = match find(&txn, key).await? {
+            Some(old) => {
+                QueryCache::delete_by_id(old.rid).exec(&txn).await?;
+                Some(old.to_query()?)
+            },
+            None => None,
+        };
+        QueryCache::insert(row).exec(&txn).await?;
+        txn.commit().await?;
+        Ok(replaced)
+    }
+
+    async fn remove(&self, key: &str) -> Result<Option<Query>> {
+        let row = match self.find(key).await? { Some(row) => row, None => return Ok(None) };
+        QueryCache::delete_by_id(row.rid).exec(&self.db).await?;
+        Ok(Some(row.to_query()?))
+    }
+
+    async fn iter(&self) -> Result<Vec<(String, Query)>> {
+        let rows = QueryCache::find().all(&self.db).await?;
+        rows.into_iter().map(|row| Ok((row.query_key.clone(), row.to_query()?))).collect()
+    }
+
+    async fn clear(&self) -> Result<()> {
+        QueryCache::delete_many().exec(&self.db).await?;
+        Ok(())
+    }
+
+    async fn len(&self) -> Result<usize> {
+        use sea_orm::PaginatorTrait;
+        Ok(QueryCache::find().count(&self.db).await? as usize)
+    }
+}
+
+/// What `SeaOrmCache::rehash()` did to the `query_cache` table
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RehashReport {
+    /// Number of rows whose `query_key_hash` was updated
+    pub rehashed: usize,
+    /// Number of rows whose `query_key_hash` was already right
+    pub unchanged: usize,
+    /// The `(rid, query_key)` of each group of rows whose keys hash alike, left untouched
+    pub collisions: Vec<Vec<(i32, String)>>,
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use sea_orm::{ConnectionTrait, Database, Schema, Statement};
+
+use super::{QueryCacheBackend, SeaOrmCache};
+use super::sea_orm::RehashReport;
+use crate::error::Result;
+use crate::models::Query;
+use crate::models::db::prelude::QueryCache;
+
+
+/// A `query_cache` table in a local SQLite file, for a durable cache without a database server. The file and table are created if missing.
+#[derive(Debug, Clone)]
+pub struct SqliteCache {
+    path: PathBuf,
+    inner: SeaOrmCache,
+}
+
+impl SqliteCache {
+
+    /// # Errors
+    /// `Error::Db` if the file cannot be opened or created, or the table cannot be created
+    pub async fn open(path: impl Into<PathBuf>) -> Result<SqliteCache> {
+        let path = path.into();
+        let db = Database::connect(format!("sqlite://{}?mode=rwc", path.display())).await?;
+
+        let backend = db.get_database_backend();
+        let mut table = Schema::new(backend).create_table_from_entity(QueryCache);
+        db.execute(backend.build(table.if_not_exists())).await?;
+        // Tables created before queries carried tags lack the column
+        let columns = db.query_all(Statement::from_string(backend, "PRAGMA table_info(query_cache)".to_string())).await?;
+        if !columns.iter().any(|column| column.try_get::<String>("", "name").is_ok_and(|name| name == "tags")) {
+            db.execute(Statement::from_string(backend, "ALTER TABLE query_cache ADD COLUMN tags json".to_string())).await?;
+        }
+
+        println!("🗳️  Cache opened at: {}", path.display());
+        Ok(SqliteCache { path, inner: SeaOrmCache::new(db) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// See `SeaOrmCache::rehash()`
+    pub async fn rehash(&self) -> Result<RehashReport> {
+        self.inner.rehash().await
+    }
+}
+
+#[async_trait]
+impl QueryCacheBackend for SqliteCache {
+    async fn get(&self, key: &str) -> Result<Option<Query>> {
+        self.inner.get(key).await
+    }
+
+    async fn put(&self, key: &str, query: &Query) -> Result<Option<Query>> {
+        self.inner.put(key, query).await
+    }
+
+    async fn remove(&self, key: &str) -> Result<Option<Query>> {
+        self.inner.remove(key).await
+    }
+
+    async fn iter(&self) -> Result<Vec<(String, Query)>> {
+        self.inner.iter().await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn len(&self) -> Result<usize> {
+        self.inner.len().await
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use super::key;
+use crate::models::{Bill, Query, QueryType};
+
+
+/// What is in a cache, and what it saved, as reported by `OpenAIAccount::cache_stats`. Costs are in CENTS
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Size of the keys and their Queries in JSON, as counted by `CachePolicy::max_bytes`
+    pub bytes: usize,
+    pub entries_by_type: HashMap<QueryType, usize>,
+    /// What the cached completions cost when they were asked
+    pub cost: f32,
+    /// Completions served from the cache, the bill's `cache_retrievals`
+    pub hits: i32,
+    /// Completions asked of OpenAI, the bill's `query_count`
+    pub misses: i32,
+    /// What the completions served from the cache would have cost, the bill's `cache_savings`
+    pub saved: f32,
+    /// The biggest entries, largest first, with their size in bytes
+    pub largest: Vec<(String, usize)>,
+    /// Groups of keys holding the same question — same label, prompt, type, model and temperature — such as an entry left behind under a key from before `rekey()`
+    pub duplicates: Vec<Vec<String>>,
+}
+
+impl CacheStats {
+
+    /// Statistics of `entries`, with the hit counts of `bill`, listing the `largest` biggest entries
+    pub(crate) fn new(entries: &[(String, Query)], bill: &Bill, largest: usize) -> CacheStats {
+        let mut entries_by_type = HashMap::new();
+        let mut sizes = Vec::with_capacity(entries.len());
+        let mut questions: HashMap<(&str, &str, QueryType, String, u32), Vec<String>> = HashMap::new();
+        for (key, query) in entries {
+            *entries_by_type.entry(query.query_type).or_insert(0) += 1;
+            sizes.push((key.clone(), entry_size(key, query)));
+            let question = (key::label(key), query.prompt.as_str(), query.query_type, query.model.to_string(), query.temperature.to_bits());
+            questions.entry(question).or_default().push(key.clone());
+        }
+        let bytes = sizes.iter().map(|(_, size)| size).sum();
+        sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        sizes.truncate(largest);
+        let mut duplicates: Vec<Vec<String>> = questions.into_values().filter(|keys| keys.len() > 1).collect();
+        for keys in &mut duplicates { keys.sort() }
+        duplicates.sort();
+
+        CacheStats {
+            entries: entries.len(),
+            bytes,
+            entries_by_type,
+            cost: entries.iter().map(|(_, query)| query.cost).sum(),
+            hits: bill.cache_retrievals,
+            misses: bill.query_count,
+            saved: bill.cache_savings,
+            largest: sizes,
+            duplicates,
+        }
+    }
+
+    /// Share of completions served from the cache, `None` before any was asked for
+    pub fn hit_rate(&self) -> Option<f64> {
+        let asked = self.hits + self.misses;
+        (asked > 0).then(|| self.hits as f64 / asked as f64)
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Entries: {} ({} bytes), worth ${:.2}", self.entries, self.bytes, self.cost / 100.0)?;
+        let mut by_type: Vec<_> = self.entries_by_type.iter().map(|(query_type, count)| format!("{query_type:?}: {count}")).collect();
+        by_type.sort();
+        writeln!(f, "By type: {}", by_type.join(", "))?;
+        match self.hit_rate() {
+            Some(rate) => writeln!(f, "Hits: {} of {} ({:.1}%), saving ${:.2}", self.hits, self.hits + self.misses, rate * 100.0, self.saved / 100.0)?,
+            None => writeln!(f, "Hits: none yet")?,
+        }
+        writeln!(f, "Largest:")?;
+        for (key, size) in &self.largest { writeln!(f, "  {size:>9} B  {key}")? }
+        writeln!(f, "Duplicates: {}", self.duplicates.len())?;
+        for keys in &self.duplicates { writeln!(f, "  {}", keys.join("  |  "))? }
+        Ok(())
+    }
+}
+
+/// Size of a cache entry: its key and its Query in JSON
+pub(crate) fn entry_size(key: &str, query: &Query) -> usize {
+    key.len() + serde_json::to_vec(query).map_or(0, |json| json.len())
+}
@@ -2,39 +2,84 @@ use reqwest::Response;
 use sea_orm::{DatabaseConnection, Database, EntityTrait, QueryFilter, ColumnTrait};
 use std::{collections::HashMap, env, };
 use crate::constants::pdf_path::DEFAULT_PDF_DIR;
-use crate::models::api_error::APIError;
+use crate::models::api_error::OpenAIErrorBody;
+use crate::error::{Error, Result};
+use crate::models::retry::RetryPolicy;
+use crate::rate_limit::{self, RateLimiter, RateLimits};
 
+use crate::ledger::{Ledger, LedgerEntry, LedgerEvent};
 use crate::models::hash::calculate_hash;
+use crate::models::request::{Function, StreamOptions};
+use crate::stream::{CompletionStream, PendingQuery};
+use crate::builder::OpenAIAccountBuilder;
+use crate::flight::InFlight;
+use crate::spending::Spending;
+use crate::fixtures::Fixtures;
+use crate::history::{self, ArchiveReason, Change, QueryHistory, Version};
+use crate::cache::{self, CacheStats, JsonFileCache, MemoryCache, QueryCacheBackend, RehashReport, RekeyReport, SeaOrmCache};
+use crate::cache::key::{self, document_hash, request_key};
+use std::sync::{Arc, Mutex, MutexGuard};
 use crate::models::{*};
 use crate::{*};
 
-use std::fs;
-use std::io;
-
-const API_URL_V1: &str = "https://api.openai.com/v1";
+use std::path::PathBuf;
 
 pub const BILL_FILEPATH: &str = "bill.json";
+pub const LEDGER_FILEPATH: &str = "ledger.jsonl";
 pub const CACHE_FILEPATH: &str = "cache.json"; // "./src/research_sets/../cache.json"
+pub const HISTORY_FILEPATH: &str = "history.jsonl";
+pub const PRICING_FILEPATH: &str = "pricing.json";
 
 
+/// A handle on an OpenAI account, its cache and its bill. <br>
+/// Clones are cheap and share the HTTP client, rate limiter, cache and bill, and every method that uses the cache or bill takes `&self`, so one account can serve concurrent requests, e.g. from `rocket::State`. Only the setters take `&mut self`.
 #[derive(Clone, Debug)]
 pub struct OpenAIAccount  { 
     /// Choose from models::gpt_models From this
-    model: GptModel,
+    pub(crate) model: GptModel,
     /// Default value looks for `CHATGPT_API_KEY` environment var
-    api_key: String,
+    pub(crate) api_key: String,
+    /// Base url, auth scheme and extra headers/query parameters of the server requests are sent to. <br> Default value is the official OpenAI api, overridable through `OPENAI_BASE_URL` and `OPENAI_ORG_ID`, see `ApiConfig::from_env()`
+    pub(crate) api: ApiConfig,
+    /// How failed requests are retried. Default value retries 5 times with exponential backoff and jitter
+    pub(crate) retry: RetryPolicy,
+    /// Client-side requests/tokens per minute limiter, shared between clones of this account. Default value is unlimited, but still honours the `x-ratelimit-*` headers
+    pub(crate) rate_limiter: RateLimiter,
+    /// Timeouts, proxy, user agent and root certificates of `client`. Default value times out connecting after 10s, waiting for OpenAI after 5 minutes, and any request after 10 minutes
+    pub(crate) http: HttpConfig,
+    /// Long-lived client built from `http`, shared between clones of this account so connections and TLS sessions are reused
+    pub(crate) client: reqwest::Client,
     /// `0.0 - 0.4`: Produces more focused, conservative, and consistent responses. <br> `0.5 - 0.7`: Strikes a balance between creativity and consistency. <br> `0.8 - 1.0`: Generates more creative, diverse, and unexpected outputs. <br> Default sets to 0.0
-    temperature: f32,
-    /// Attribute used to save and retrieve running metrics, which are running totals of Query metrics. 
-    /// <br> This variable is serialized into and deserialized from this OpenAIAccount's `.cache_filepath` attribute. The running total can be reset with ...
-    /// <br> See struct `Bill` for a list of what is tracked.
-    bill: Bill,
+    pub(crate) temperature: f32,
+    /// Running totals of Query metrics, as of this account's last event. 
+    /// <br> Read from `ledger` on construction and kept up to date with every event this account records. The running total can be reset with `.reset_bill()`
+    /// <br> See struct `Bill` for a list of what is tracked. Clones of this account share and update the same bill.
+    pub(crate) bill: Arc<Mutex<Bill>>,
     /// Attribute used to save and retrieve Query metrics. 
-    /// This variable is serialized into and deserialized from CACHE_FILEPATH constant.
+    /// The backend is chosen at construction, see `QueryCacheBackend`. Default value is a `MemoryCache`, while `OpenAIAccount::new()` uses a `JsonFileCache` at CACHE_FILEPATH.
     /// If a query completion is sent, and the prompt is already found in the cache, the cached response is retrieved, and a new API request is not sent.
     /// Keys are prompts, values are Queries (which themselves hold the prompt, model, etc.)
-    pub cache: HashMap<String,Query>,
-    
+    pub(crate) cache: Arc<dyn QueryCacheBackend>,
+    /// TTLs and size limits of `cache`, enforced whenever a Query is cached. Default value keeps every entry forever
+    pub(crate) cache_policy: CachePolicy,
+    /// Where every billable event is recorded, see `Ledger`. `None` keeps the bill in memory only
+    pub(crate) ledger: Option<Ledger>,
+    /// Where overwritten, evicted and replaced queries are kept as versions of their key, see `QueryHistory`. `None` discards them
+    pub(crate) history: Option<QueryHistory>,
+    /// Cache keys whose completion is being requested, shared between clones of this account so identical concurrent requests are sent once, see `InFlight`
+    pub(crate) in_flight: InFlight,
+    /// Whether completions missing from the cache are asked of OpenAI, recorded, replayed or refused, see `Mode`. Default value asks OpenAI
+    pub(crate) mode: Mode,
+    /// Prices the cost of every Query is computed with. Default value is the built-in `PricingTable`, which `OpenAIAccount::new()` extends with PRICING_FILEPATH if there is one
+    pub(crate) pricing: PricingTable,
+    /// Attached to every Query this account completes and to its events in the ledger, see `Tags`. Default value is empty
+    pub(crate) tags: Tags,
+    /// The most tokens any completion of this account may use, sent as the `max_tokens` of every request. Default value is `None`, which lets a completion fill the context window, so `Budgets` count each request as if it would
+    pub(crate) max_tokens: Option<u32>,
+    /// Limits on what the users, libraries and jobs in a request's tags may spend, checked before it is sent, see `Budgets`. Default value is `None`, which leaves spending unlimited
+    pub(crate) budgets: Option<Budgets>,
+    /// What the users, libraries and jobs with budgets have spent, and hold for requests in flight, shared between clones of this account, see `Spending`
+    pub(crate) spending: Spending,
 }
 
 
@@ -42,122 +87,100 @@ pub struct OpenAIAccount  {
 impl Default for OpenAIAccount {
     fn default() -> OpenAIAccount {
         OpenAIAccount {
-            api_key: env::var("CHATGPT_API_KEY").unwrap().to_string(),
+            api_key: env::var("CHATGPT_API_KEY").unwrap_or_default(),
+            api: ApiConfig::from_env(),
+            retry: RetryPolicy::default(),
+            rate_limiter: RateLimiter::default(),
+            http: HttpConfig::default(),
+            client: HttpConfig::default().client().unwrap_or_default(),
             temperature: 0.0,
-            cache: HashMap::new(),
-            bill: Bill {..Default::default()},
+            cache: Arc::new(MemoryCache::new()),
+            cache_policy: CachePolicy::default(),
+            bill: Arc::new(Mutex::new(Bill::default())),
             model: GptModel::Gpt35Turbo16k,
+            ledger: None,
+            history: None,
+            in_flight: InFlight::default(),
+            mode: Mode::default(),
+            pricing: PricingTable::default(),
+            tags: Tags::default(),
+            max_tokens: None,
+            budgets: None,
+            spending: Spending::default(),
         }
     }
 }
 
+// An account is shared between threads, e.g. as server state
+const _: fn() = || {
+    fn shareable<T: Clone + Send + Sync + 'static>() {}
+    shareable::<OpenAIAccount>();
+};
+
 /// The atoms of `OpenAIAccount` functionality, such as initiators, getters, setters, etc., for combination in larger functions
 impl OpenAIAccount {
     
     /// Create a new instance of the OpenAIAccount, taking a `GptModel`, temperature
     /// <br> `0.0 - 0.4`: Produces more focused, conservative, and consistent responses. <br> `0.5 - 0.7`: Strikes a balance between creativity and consistency. <br> `0.8 - 1.0`: Generates more creative, diverse, and unexpected outputs. <br> Default sets to 0.0
-    /// <br> Initializing an `OpenAIAccount` with .new() clears the backup ("graveyard"). Because of this, initializing the client twice within a project endpoint is not recommended, instead, there should be sufficient getters-setters to make adjustments midway through an analysis.
+    /// <br> The api key is read from `CHATGPT_API_KEY`, the api settings from `ApiConfig::from_env()`, the ledger, cache and query history are kept in LEDGER_FILEPATH, CACHE_FILEPATH and HISTORY_FILEPATH in the working directory, and an old BILL_FILEPATH is carried into the ledger. To choose any of these, or to keep everything in memory, use `OpenAIAccount::builder()` instead.
     /// 
     /// # Errors
-    /// Assumes access to files at BILL_FILEPATH and CACHE_FILEPATH variables
+    /// `Error::Env` if `CHATGPT_API_KEY` is not set, `Error::Io` if the files at BILL_FILEPATH or CACHE_FILEPATH cannot be created, and `Error::Corrupt` if either cannot be parsed (rather than starting over with an empty cache)
     /// <br>
     /// <br> 
-    pub fn new(model: GptModel, temperature: f32, ) -> OpenAIAccount {
-        let api_key = env::var("CHATGPT_API_KEY").unwrap().to_string();
-        // Read the bill into memory or else initialize empty
-        
-        let bill = match fs::File::open(BILL_FILEPATH) {
-            Ok(f) => {
-                let reader = io::BufReader::new(f);
-                // Read the JSON contents of the file as an instance of...
-                let bill: Bill = serde_json::from_reader(reader).unwrap_or_else(|e| {println!("🧾 Initializing client with default blank bill due to:  ❌  {e}") ; Bill {..Default::default()}});
-                println!("🧾 Bill read from: {BILL_FILEPATH}");
-                bill
-            },
-            Err(_) => {
-                fs::File::create(BILL_FILEPATH).expect("Creation of Bill file, after having not found any file");
-                println!("🧾 Empty Bill created at: {BILL_FILEPATH}");
-                Bill {..Default::default()}
-            },
-        };
-
-        // Read the cache into memory or else initialize empty
-        let cache: HashMap<String, Query> = match fs::File::open(CACHE_FILEPATH) {
-            Ok(f) => {
-                let reader = io::BufReader::new(f);
-                // Read the JSON contents of the file as an instance of...
-                let cache: HashMap<String, Query> = serde_json::from_reader(reader).unwrap_or_else(|e| { if let serde_json::error::Category::Eof = e.classify() {HashMap::new()} else { println!("🗳️  Initializing client with blank cache due to:  ❌  {e}") ; HashMap::new()}  });
-                println!("🗳️  Cache read from: {CACHE_FILEPATH}");
-                cache
-            },
-            Err(_) => {
-                fs::File::create(CACHE_FILEPATH).expect("Creation of Cache file, after having not found any file");
-                println!("🗳️  Empty Cache created at: {CACHE_FILEPATH}");
-                HashMap::new()
-            },
-        };
-
-        let _graveyard = std::fs::OpenOptions::new().create(true).truncate(true).write(true).open("graveyard.json").expect("access to graveyard file");
-        println!("🪦  Graveyard backups cleared.");
+    pub fn new(model: GptModel, temperature: f32, ) -> Result<OpenAIAccount> {
+        OpenAIAccountBuilder::from_env()?
+            .model(model)
+            .temperature(temperature)
+            .api_config(ApiConfig::from_env())
+            .default_files()
+            .build()
+    }
 
-        println!("🌡️  Model initialized at temperature {temperature}");
-        OpenAIAccount {
-            bill,
-            cache,
-            model,
-            temperature,
-            api_key,
-            ..Default::default()
-        }
+    /// Start building an account that sends requests with `api_key`, and touches no files or environment variables unless told to. See `OpenAIAccountBuilder`
+    pub fn builder(api_key: impl Into<String>) -> OpenAIAccountBuilder {
+        OpenAIAccountBuilder::new(api_key)
     }
 
     /// Sends the prompt as the first message, and returns the chat completion response.
-    /// <br> Checks cache for presence of prompt, and returns the cache value if present instead of repeating request.
+    /// <br> Checks cache for presence of the request (prompt, model and temperature), and returns the cache value if present instead of repeating request.
     /// <br> Inputting a model will use that model, otherwise `None` will default to the model used in the .new() initiator.
-    pub async fn get_completion(&mut self, prompt: String, model: Option<GptModel>) -> Result<Query, Status> {
+    pub async fn get_completion(&self, prompt: String, model: Option<GptModel>) -> Result<Query> {
 
         let model = match model {Some(m) => m, None => self.model};
+        let tags = self.tags.clone();
+        let req = ChatCompletionRequest::from_prompt(model, prompt.clone(), self.temperature).with_max_tokens(self.max_tokens);
+        let cache_key = request_key(&prompt, &req, None)?;
 
-        let query = match self.check_cache(&prompt, QueryType::PromptCompletion) {
+        // Concurrent callers with the same key wait here, then find this call's Query in the cache
+        let _flight = self.in_flight.acquire(&cache_key).await;
+        let query = match self.check_cache(&cache_key, QueryType::PromptCompletion).await? {
             // If found in cache, retrieve the query
             Some(query) => {
                 let mut query = query.clone(); 
                 query.from_cache = true;
-                self.bill.cache_retrievals += 1; 
-                self.update_bill(None); 
+                query.tags = tags;
+                self.record_cache_hit(&cache_key, &query)?;
                 println!("--[Cached Answer]--");
                 query
             },
             // If absent, send to OpenAI
             None => {
                 let from_cache = false;
-                let req = ChatCompletionRequest {
-                    model: model.to_string(),
-                    messages: vec![ChatCompletionMessage {
-                        role: MessageRole::user,
-                        content: Some(prompt.clone()),
-                        name: None,
-                        function_call: None,
-                    }],
-                    functions: None,
-                    function_call: None,
-                    temperature: Some(self.temperature)
-                };
-
                 let start_time = std::time::Instant::now();
-                let response = match self.send_completion_request(req).await {Ok(res) => res, Err(e) => return Err(Status::Error(e.to_string()))};
-                let process_time = start_time.elapsed().as_secs();
+                let response = self.complete(req, &cache_key, &tags).await?;
+                let process_time = start_time.elapsed().as_millis() as u64;
 
                 // Build Query from Response
-                let query = Query {prompt: prompt.clone(), response: response.clone(), query_type: QueryType::PromptCompletion, cost: response.cost(&model), process_time, model, temperature: self.temperature, from_cache };
-                // Add Query to Cache
-                self.cache_query(&prompt, &query);
+                let query = Query {prompt: prompt.clone(), response: response.clone(), query_type: QueryType::PromptCompletion, cost: self.cost_of(&response, model), process_time, model, temperature: self.temperature, from_cache, cached_at: None, last_used: None, tags };
                 // Add data to Bill
-                self.update_bill(Some(&query));
+                self.update_bill(&cache_key, &query)?;
+                // Add Query to Cache
+                self.cache_query(&cache_key, &query).await?;
 
 
-                println!("--[Bill so far: ${:.2}]--", self.bill.cost / 100.0);
-                println!("--[Took: {}, Cost: ¢{:.4}]--", process_time, (response.cost(&model)));
+                println!("--[Bill so far: ${:.2}]--", self.bill().cost / 100.0);
+                println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, query.cost);
                 query
             },
         };
@@ -167,122 +190,560 @@ impl OpenAIAccount {
 
     }
 
+    /// Streaming counterpart of `.get_completion()`. Returns a `CompletionStream` yielding content deltas as OpenAI generates them.
+    /// <br> Once drained, pass the stream to `.finish_stream()` to get the completed `Query`, which is then cached and billed exactly as `.get_completion()` would.
+    /// <br> If the prompt is found in cache, the stream yields the cached content as a single delta and no request is sent.
+    pub async fn get_completion_stream(&self, prompt: String, model: Option<GptModel>) -> Result<CompletionStream> {
+
+        let model = match model {Some(m) => m, None => self.model};
+        let tags = self.tags.clone();
+        let req = ChatCompletionRequest::from_prompt(model, prompt.clone(), self.temperature).with_max_tokens(self.max_tokens);
+        let cache_key = request_key(&prompt, &req, None)?;
+
+        // The key stays in flight until the stream is finished or dropped
+        let flight = self.in_flight.acquire(&cache_key).await;
+        if let Some(query) = self.check_cache(&cache_key, QueryType::PromptCompletion).await? {
+            return Ok(CompletionStream::from_cache(cache_key.clone(), Query { tags, ..query }))
+        }
+
+        let pending = PendingQuery { cache_key, prompt, query_type: QueryType::PromptCompletion, model, temperature: self.temperature, tags, start_time: std::time::Instant::now() };
+        Ok(self.complete_stream(req, pending).await?.holding(flight))
+    }
+
+    /// Turns a drained `CompletionStream` into a `Query`. A stream that was served from cache counts as a cache retrieval, otherwise the Query is cached and its usage added to the bill.
+    /// <br> Returns `Error::Usage` if the stream has not yet yielded its last delta, and `Error::Stream` if it ended on an error or was cut short, so a truncated completion is neither cached nor billed.
+    pub async fn finish_stream(&self, stream: CompletionStream) -> Result<Query> {
+
+        if let Some((cache_key, mut query)) = stream.cached {
+            query.from_cache = true;
+            self.record_cache_hit(&cache_key, &query)?;
+            println!("--[Cached Answer]--");
+            return Ok(query)
+        }
+
+        let pending = match &stream.query { Some(pending) => pending.clone(), None => return Err(Error::Usage("stream carries neither a cached nor a pending query".to_string())) };
+        let response = match stream.response() {
+            Ok(response) => response,
+            Err(e) => { self.spending.release(&pending.cache_key); return Err(e) },
+        };
+        let process_time = pending.start_time.elapsed().as_millis() as u64;
+
+        let query = Query { prompt: pending.prompt, cost: self.cost_of(&response, pending.model), response, process_time, model: pending.model, query_type: pending.query_type, temperature: pending.temperature, from_cache: false, cached_at: None, last_used: None, tags: pending.tags };
+        self.update_bill(&pending.cache_key, &query)?;
+        if let (Some(req), Mode::Record(path)) = (&stream.recording, &self.mode) { Fixtures::new(path).record(req, &query.response)?; }
+        self.cache_query(&pending.cache_key, &query).await?;
+
+        println!("--[Bill now shows: ${:.2}]--", self.bill().cost / 100.0);
+        println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, query.cost);
+        Ok(query)
+    }
+
+    /// Send the next user message of a `Conversation`, along with its whole history. The assistant's reply is appended to the conversation, and the turn's Query is both returned and pushed onto `conversation.queries`.
+    /// <br> Each turn is cached under a key labelled `conversation.cache_label()`, whose digest covers the full history, model and temperature, so replaying the same conversation is served from cache turn by turn.
+    /// <br> If the request fails, the user message is removed again so the conversation can be retried as is.
+    pub async fn converse(&self, conversation: &mut Conversation, message: String) -> Result<Query> {
+
+        let model = conversation.model.unwrap_or(self.model);
+        conversation.push(MessageRole::user, &message);
+
+        let query = match self.converse_turn(conversation, message, model, None).await {
+            Ok(query) => query,
+            Err(e) => {
+                conversation.messages.pop();
+                return Err(e)
+            }
+        };
+
+        conversation.messages.push(reply_message(&query));
+        conversation.queries.push(query.clone());
+
+        Ok(query)
+    }
+
+    /// `.converse()` with tools: the functions of `tools` are sent along with the conversation, and whenever the model answers with a `function_call`, the matching handler is run and its result is sent back as a `function` message. This repeats until the model answers normally, whose Query is returned.
+    /// <br> Every round (function calls, function results and the final reply) is appended to the conversation, and every round's Query is pushed onto `conversation.queries` so the whole exchange is billed with the conversation.
+    /// <br> If a request or a handler fails, or the model is still calling functions after `tools.max_rounds` calls, the messages of this exchange are removed from the conversation again. The Queries already paid for are kept.
+    pub async fn converse_with_tools(&self, conversation: &mut Conversation, message: String, tools: &ToolBox) -> Result<Query> {
+
+        let model = conversation.model.unwrap_or(self.model);
+        let checkpoint = conversation.messages.len();
+        // An empty `functions` list is rejected by OpenAI, so a conversation without tools sends none
+        let functions = if tools.is_empty() { None } else { Some(tools.functions()) };
+        conversation.push(MessageRole::user, &message);
+
+        let mut prompt = message;
+        let mut calls = 0;
+        loop {
+            let query = match self.converse_turn(conversation, prompt, model, functions.as_deref()).await {
+                Ok(query) => query,
+                Err(e) => { conversation.messages.truncate(checkpoint); return Err(e) }
+            };
+            let reply = reply_message(&query);
+            conversation.messages.push(reply.clone());
+            conversation.queries.push(query.clone());
+
+            let call = match reply.function_call {
+                Some(call) => call,
+                None => return Ok(query),
+            };
+            if calls == tools.max_rounds {
+                conversation.messages.truncate(checkpoint);
+                return Err(Error::Usage(format!("the model was still calling functions after {} calls", tools.max_rounds)))
+            }
+            calls += 1;
+
+            println!("--[🔧 Calling {}({})]--", call.name.as_deref().unwrap_or_default(), call.arguments.as_deref().unwrap_or_default());
+            let result = match tools.call(&call).await {
+                Ok(result) => result,
+                Err(e) => { conversation.messages.truncate(checkpoint); return Err(e) }
+            };
+            conversation.messages.push(ChatCompletionMessage { role: MessageRole::function, content: Some(result.clone()), name: call.name, function_call: None });
+            prompt = result;
+        }
+    }
+
+    /// Send the conversation as it stands, offering `functions` if any. The functions are part of the cache key, as the same history may be answered differently depending on the tools on offer.
+    async fn converse_turn(&self, conversation: &Conversation, message: String, model: GptModel, functions: Option<&[Function]>) -> Result<Query> {
+
+        let tags = self.tags.clone();
+
+        let req = ChatCompletionRequest {
+            model: model.to_string(),
+            messages: conversation.messages.clone(),
+            functions: functions.map(|functions| functions.to_vec()),
+            function_call: None,
+            temperature: Some(self.temperature),
+            max_tokens: self.max_tokens,
+            stream: None,
+            stream_options: None,
+        };
+        let cache_key = request_key(&conversation.cache_label(), &req, None)?;
+
+        let _flight = self.in_flight.acquire(&cache_key).await;
+        let query = match self.check_cache(&cache_key, QueryType::Conversation).await? {
+            Some(query) => {
+                let mut query = query.clone();
+                query.from_cache = true;
+                query.tags = tags;
+                self.record_cache_hit(&cache_key, &query)?;
+                println!("--[Cached Answer]--");
+                query
+            },
+            None => {
+                let from_cache = false;
+                let start_time = std::time::Instant::now();
+                let response = self.complete(req, &cache_key, &tags).await?;
+                let process_time = start_time.elapsed().as_millis() as u64;
+
+                let query = Query { prompt: message, response: response.clone(), query_type: QueryType::Conversation, cost: self.cost_of(&response, model), process_time, model, temperature: self.temperature, from_cache, cached_at: None, last_used: None, tags };
+                self.update_bill(&cache_key, &query)?;
+                self.cache_query(&cache_key, &query).await?;
+
+                println!("--[Conversation \"{}\" so far: ¢{:.4}]--", conversation.title, conversation.cost() + query.cost);
+                println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, query.cost);
+                query
+            },
+        };
+
+        Ok(query)
+    }
+
     /// Checks for presence of a Query at the Prompt, returns `Some(Query)` if found in cache, and `None` if absent. 
     /// Converts prompt input to a more uniform format that is used for keys. <br>
-    /// - `cache_key` should be either a prompt, to retrive a prompt completion, or a pdf title, to retrieve a summary
+    /// - `cache_key` should be made by `cache::key::request_key()`, labelled with either a prompt, to retrive a prompt completion, or a `"{pdf title} - {battery stamp}"`, to retrieve a summary
     /// - When set to `PromptCompletion`, the cache_key is regularized for whitespace, and lowercased.
     /// - When set to `PdfCompletion`, the cache_key is used as passed, supposedly in title case
-    pub fn check_cache(&self, cache_key: &String, query_type: QueryType) -> Option<&Query> {
+    pub async fn check_cache(&self, cache_key: &String, query_type: QueryType) -> Result<Option<Query>> {
         // Make the prompt more uniform
         let key = match query_type {
             QueryType::PromptCompletion => cache_key.to_lowercase().replace("\n", " "),
             QueryType::PdfCompletion => cache_key.to_string(),
             QueryType::MetaCompletion => cache_key.to_string(),
+            QueryType::Conversation => cache_key.to_string(),
         };
-        let find = self.cache.get(&key); // if None -> return None
-        
-        find
+        let now = chrono::Utc::now().timestamp_millis();
+        match self.cache.get(&key).await? {
+            // An expired entry is a miss, and is evicted when its replacement is cached
+            Some(query) if self.cache_policy.is_expired(&query, now) => {
+                println!("⌛ Cached answer expired at key: \"{key}\"");
+                Ok(None)
+            },
+            Some(query) => {
+                self.cache.touch(&key, now).await?;
+                Ok(Some(query))
+            },
+            None => Ok(None),
+        }
     }
 
-    /// Resets both the cache file and in-memory cache to empty
-    pub fn clear_cache(&mut self) {
-        self.cache.clear();
-        match fs::File::create(CACHE_FILEPATH) { Ok(_)=>(), Err(e)=>{println!("\nclear_cache() had trouble initializing a new blank cache file at '{CACHE_FILEPATH}' : \n❌  {e}")}};
-        println!("🗳️  Cache cleared at: {CACHE_FILEPATH}");
+    /// The TTLs and size limits the cache is kept to, see `CachePolicy`
+    pub fn cache_policy(&self) -> &CachePolicy {
+        &self.cache_policy
     }
 
-    pub fn remove_from_cache(&mut self, cache_key: String) -> Option<(String, Query)> {
-        let entry = self.cache.remove_entry(&cache_key);
-        
+    /// Keep the cache to `policy` from now on, evicting whatever it already exceeds. Returns the number of entries evicted
+    pub async fn set_cache_policy(&mut self, policy: CachePolicy) -> Result<usize> {
+        self.cache_policy = policy;
+        self.evict_cache().await
+    }
+
+    /// Evict the entries that have expired, then the least recently used (or oldest, see `Eviction`) until the cache fits the account's `CachePolicy`. Evicted entries are archived in the query history and counted in the bill.
+    /// <br> Called whenever a Query is cached; call it directly to sweep expired entries from a cache that is only read from. Returns the number of entries evicted
+    pub async fn evict_cache(&self) -> Result<usize> {
+        self.evict_cache_sparing(None).await
+    }
+
+    /// `.evict_cache()`, evicting the entry at `spared` last whatever its rank, so a Query is never evicted by its own caching while anything else can go
+    async fn evict_cache_sparing(&self, spared: Option<&str>) -> Result<usize> {
+        if self.cache_policy.is_unbounded() { return Ok(0) }
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let entries = self.cache.iter().await?;
+        let (mut evicted, mut kept): (Vec<_>, Vec<_>) = entries.into_iter().partition(|(_, query)| self.cache_policy.is_expired(query, now));
+        let expired = evicted.len();
+        kept.sort_by_key(|(key, query)| if Some(key.as_str()) == spared { i64::MAX } else { self.cache_policy.rank(query) });
+        let sizes: Vec<usize> = kept.iter().map(|(key, query)| cache::stats::entry_size(key, query)).collect();
+
+        let (mut entries, mut bytes) = (kept.len(), sizes.iter().sum::<usize>());
+        let mut over = 0;
+        while over < kept.len() && self.cache_policy.is_over(entries, bytes) {
+            entries -= 1;
+            bytes -= sizes[over];
+            over += 1;
+        }
+        evicted.extend(kept.into_iter().take(over));
+        if evicted.is_empty() { return Ok(0) }
+
+        let keys: Vec<String> = evicted.iter().map(|(key, _)| key.clone()).collect();
+        self.cache.remove_many(&keys).await?;
+        for (index, (key, query)) in evicted.iter().enumerate() {
+            self.archive(key, query, if index < expired { ArchiveReason::Expiry } else { ArchiveReason::Eviction })?;
+        }
+        self.record(LedgerEvent::Eviction { at: now, count: evicted.len() as i32 })?;
+
+        println!("🗳️  Evicted {} cache entries, {entries} remain.", evicted.len());
+        if self.history.is_some() { println!("📜 The evicted queries can be found in the query history."); }
+        Ok(evicted.len())
+    }
+
+    /// The backend this account caches Queries in, see `QueryCacheBackend`
+    pub fn cache(&self) -> &Arc<dyn QueryCacheBackend> {
+        &self.cache
+    }
+
+    /// The cache entries `filter` picks, oldest first. Entries cached before timestamps were kept come first, in key order
+    pub async fn list_cache(&self, filter: &CacheFilter) -> Result<Vec<(String, Query)>> {
+        let mut entries: Vec<(String, Query)> = self.cache.iter().await?.into_iter().filter(|(_, query)| filter.matches(query)).collect();
+        entries.sort_by(|a, b| a.1.cached_at.cmp(&b.1.cached_at).then_with(|| a.0.cmp(&b.0)));
+        Ok(entries)
+    }
 
-        match entry {
-            Some(entry) => {
+    /// Sizes, hit rate, savings, the `largest` biggest entries and duplicated questions of the cache, see `CacheStats`. Hits and savings are read from the bill
+    pub async fn cache_stats(&self, largest: usize) -> Result<CacheStats> {
+        let entries = self.cache.iter().await?;
+        Ok(CacheStats::new(&entries, &self.get_bill(), largest))
+    }
+
+    /// Copy the cache entries `filter` picks into a `JsonFileCache` at `path`, e.g. to hand a subset to a colleague or to seed a CI fixture. Returns how many were copied. <br>
+    /// Entries already in the file are kept, unless the export has an entry with the same key
+    pub async fn export_cache(&self, filter: &CacheFilter, path: impl Into<PathBuf>) -> Result<usize> {
+        let entries = self.list_cache(filter).await?;
+        let export = JsonFileCache::open(path)?;
+        for (key, query) in &entries {
+            export.put(key, query).await?;
+        }
+        export.compact()?;
+        println!("🗳️  Exported {} cache entries to: {}", entries.len(), export.path().display());
+        Ok(entries.len())
+    }
+
+    /// Empties the cache backend
+    pub async fn clear_cache(&self) -> Result<()> {
+        self.cache.clear().await
+    }
+
+    pub async fn remove_from_cache(&self, cache_key: String) -> Result<Option<(String, Query)>> {
+        match self.cache.remove(&cache_key).await? {
+            Some(query) => {
                 println!("🗳️  Removed cache entry at key: \"{cache_key}\"");
-                // Update the cache file
-                let cache_file = match fs::OpenOptions::new().create(true).truncate(true).write(true).open(CACHE_FILEPATH) {Ok(f)=>f, Err(e)=>panic!("🗳️  Could not re-write cache file after removal at {CACHE_FILEPATH}, due to error:  ❌  {e}")};
-                serde_json::to_writer_pretty(&cache_file, &self.cache).expect("Serialization of cache to cache file");
-                Some(entry)
+                Ok(Some((cache_key, query)))
             },
-            None => None
+            None => Ok(None)
         }
     }
 
-    /// Adds a query to the current in-memory cache, and saves in-memory cache to the cache file <br>
+    /// Move the entries cached before keys covered the whole request to their request keys, see `cache::rekey()`. Battery completions look for their source pdf in `pdf_dir`, or `DEFAULT_PDF_DIR` if None.
+    pub async fn migrate_cache_keys(&self, pdf_dir: Option<String>) -> Result<RekeyReport> {
+        let dir = pdf_dir.unwrap_or_else(|| DEFAULT_PDF_DIR.to_string());
+        cache::rekey(self.cache.as_ref(), &dir).await
+    }
+
+    /// Adds a query to the cache backend <br>
     /// This will overwrite when called outside of a context that has checked the cache with `self.check_cache`
-    /// ```
-    /// match self.check_cache_for_prompt(&prompt) {
+    /// ```text
+    /// let cache_key = request_key(&prompt, &req, None)?;
+    /// match self.check_cache(&cache_key, QueryType::PromptCompletion).await? {
     ///     Some(query) => query,
     ///     None => {
     ///     /* Having found None in cache, make request to OpenAI and process Response into a Query */
-    ///     self.cache_query(&prompt, &query);
+    ///     self.update_bill(&cache_key, &query)?;
+    ///     self.cache_query(&cache_key, &query).await?;
     ///     }
     /// ``` 
     /// <br>
-    /// - Cache key should be the prompt for a PromptCompletion query, or a "{title} - {battery_stamp}" pair for battery based completions.
-    pub fn cache_query(&mut self, cache_key: &String, query: &Query) -> () {
+    /// - The Query is stamped with the time it was cached, and the cache is then brought within its `CachePolicy`, see `.evict_cache()`.
+    /// - Cache key should be made by `cache::key::request_key()`, labelled with the prompt for a PromptCompletion query, or a "{title} - {battery_stamp}" pair for battery based completions.
+    pub async fn cache_query(&self, cache_key: &String, query: &Query) -> Result<()> {
         // Make the key uniform if it is a prompt completion
         let cache_key = if let QueryType::PromptCompletion = query.query_type {cache_key.to_lowercase().replace("\n", " ")} else {cache_key.to_string()};
-        // Add to self.cache -- checking if something was overwritten, and placing into backup file if so
-        match self.cache.insert(cache_key, query.clone()) {None => (), Some(query)=> { 
-            let graveyard = std::fs::OpenOptions::new().create(true).append(true).open("graveyard.json").expect("access to graveyard file");
-            serde_json::to_writer_pretty(graveyard, &query).expect("Serialization of an overwritten model to the graveyard");
+        let now = chrono::Utc::now().timestamp_millis();
+        let query = Query { cached_at: Some(now), last_used: None, ..query.clone() };
+        // Add to the cache -- checking if something was overwritten, and placing it into the history if so
+        match self.cache.put(&cache_key, &query).await? {None => (), Some(query) if self.cache_policy.is_expired(&query, now) => {
+            self.archive(&cache_key, &query, ArchiveReason::Expiry)?;
+            self.record(LedgerEvent::Eviction { at: now, count: 1 })?;
+        }, Some(query)=> { 
+            self.archive(&cache_key, &query, ArchiveReason::Overwrite)?;
             println!("\n\n");
             println!("🗳️  Caching a query resulted in an overwrite."); 
-            println!("🪦  The overwritten query can be found in the graveyard file.");
+            if self.history.is_some() { println!("📜 The overwritten query can be found in the query history."); }
         }};
-        // Save the state of self.cache to file
-        let cache = match fs::OpenOptions::new().create(true).truncate(true).write(true).open(CACHE_FILEPATH) {Ok(f)=>f, Err(e)=>panic!("🗳️  Could not cache query at {CACHE_FILEPATH}, due to error:  ❌  {e}")};
-        serde_json::to_writer_pretty(&cache, &self.cache).expect("Serialization of cache to cache file");
+        self.evict_cache_sparing(Some(&cache_key)).await?;
+        Ok(())
+    }
+
+    /// Count `event` in the bill, and append it to the ledger if the account has one. A completion paid for settles the budget reservation made for it, see `.preflight()`
+    fn record(&self, event: LedgerEvent) -> Result<()> {
+        self.bill().add(&event);
+        let recorded = match &self.ledger { Some(ledger) => ledger.record(&event), None => Ok(()) };
+        if let LedgerEvent::Query(entry) = &event {
+            if !entry.cache_hit { self.spending.settle(&entry.key, recorded.is_err().then_some(entry)) }
+        }
+        recorded
+    }
+
+    /// The bill shared by this account and its clones. Never held across an `.await`
+    fn bill(&self) -> MutexGuard<'_, Bill> {
+        self.bill.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Keep a Query leaving the cache at `key` as a version in the query history, if the account has one
+    fn archive(&self, key: &str, query: &Query, reason: ArchiveReason) -> Result<()> {
+        match &self.history { Some(history) => history.archive(key, query, reason), None => Ok(()) }
+    }
+
+    /// The query history of this account, if it has one
+    pub fn history(&self) -> Option<&QueryHistory> {
+        self.history.as_ref()
+    }
+
+    /// The past versions of the cache entry at `cache_key`, oldest first. Empty if the account keeps no history
+    pub fn query_versions(&self, cache_key: &str) -> Result<Vec<Version>> {
+        match &self.history { Some(history) => history.versions(cache_key), None => Ok(Vec::new()) }
+    }
+
+    /// The fields that changed from version `from` to version `to` of the query history, see `history::diff()`
+    /// # Errors
+    /// `Error::Usage` if either version does not exist
+    pub fn diff_versions(&self, from: usize, to: usize) -> Result<Vec<Change>> {
+        history::diff(&self.version(from)?.query, &self.version(to)?.query)
+    }
+
+    /// The fields that changed from version `id` to what is cached at its key now
+    /// # Errors
+    /// `Error::Usage` if the version does not exist, or nothing is cached at its key
+    pub async fn diff_with_cache(&self, id: usize) -> Result<Vec<Change>> {
+        let version = self.version(id)?;
+        let current = self.cache.get(&version.key).await?.ok_or_else(|| Error::Usage(format!("nothing is cached at \"{}\"", version.key)))?;
+        history::diff(&version.query, &current)
+    }
+
+    /// Put version `id` of the query history back into the cache at its key. Whatever was cached there is archived in turn, with reason `Restore`, so a restore can itself be undone. Returns the restored Query
+    /// # Errors
+    /// `Error::Usage` if the version does not exist
+    pub async fn restore_version(&self, id: usize) -> Result<Query> {
+        let version = self.version(id)?;
+        if let Some(replaced) = self.cache.put(&version.key, &version.query).await? {
+            self.archive(&version.key, &replaced, ArchiveReason::Restore)?;
+        }
+        println!("📜 Restored version {id} at key: \"{}\"", version.key);
+        Ok(version.query)
+    }
+
+    fn version(&self, id: usize) -> Result<Version> {
+        let history = self.history.as_ref().ok_or_else(|| Error::Usage("the account keeps no query history".to_string()))?;
+        history.get(id)?.ok_or_else(|| Error::Usage(format!("there is no version {id} in {}", history.path().display())))
     }
 
+    /// The bill so far, computed from the ledger, so including what accounts in other processes sharing the ledger file have recorded
     pub fn get_bill(&self) -> Bill {
-        self.bill.clone()
+        let ledger = match &self.ledger { Some(ledger) => ledger, None => return self.bill().clone() };
+        match ledger.bill() {
+            Ok(bill) => bill,
+            Err(e) => {
+                println!("❌ Showing this account's copy of the bill: {e}");
+                self.bill().clone()
+            },
+        }
+    }
+
+    /// Where this account records its billable events, if it keeps a ledger. Use it to break spending down by day, month, model or tag
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
     }
 
-    /// Bill state is read on ::new(), and stored inside instance. Calling update_bill fully overwrites the bill file.
-    /// <br> Passing a query will add that query's usage data to the running bill before writing to file, while passing none will simply write the state of the bill to file. <br>Usually it is called with a Query as the update date, but there are times when one field is alterted directly, and the file is updated to match (cache_retrievals)
-    pub fn update_bill(&mut self, query: Option<&Query>) -> () {
+    /// The prompt tokens of `req`, counted locally, and the most it can cost at today's price of its model, with a completion of `max_tokens`, or else filling the context window. See `Estimate`. Nothing is sent
+    /// # Errors
+    /// `Error::UnknownModel` if the request's model is not a `GptModel`
+    pub fn estimate(&self, req: &ChatCompletionRequest) -> Result<Estimate> {
+        let model = GptModel::from_string(&req.model)?;
+        let prompt_tokens = tokenizer::count_request(req)?;
+        let context_window = model.context_window();
+        let max_completion_tokens = req.max_tokens.map_or(context_window.saturating_sub(prompt_tokens), |max_tokens| max_tokens as usize);
+        let usage = req_and_res::Usage { prompt_tokens: prompt_tokens as i32, completion_tokens: max_completion_tokens as i32, total_tokens: (prompt_tokens + max_completion_tokens) as i32 };
+        let max_cost = self.pricing.cost_in_cents(model, &usage, chrono::Utc::now().timestamp_millis());
+        Ok(Estimate { model, prompt_tokens, max_completion_tokens, context_window, max_cost, cached: false })
+    }
 
-        // Take the state of the bill and update it with the data from the Response if a Query was passed
-        if let Some(query) = query { 
-            let used = query.response.usage.clone();
+    /// What each user, library and job in `tags` has spent in the current period of its limit, and holds for requests in flight, see `BudgetUsage`. Empty if the account has no budgets
+    /// # Errors
+    /// `Error::Io` or `Error::Corrupt` if the ledger cannot be read
+    pub fn budget_usage(&self, tags: &Tags) -> Result<Vec<BudgetUsage>> {
+        let (budgets, ledger) = match (&self.budgets, &self.ledger) { (Some(budgets), Some(ledger)) => (budgets, ledger), _ => return Ok(Vec::new()) };
+        self.spending.usage(ledger, budgets, tags)
+    }
 
-            // Add to the bill the used amounts
-            self.bill.completion_tokens += used.completion_tokens;
-            self.bill.prompt_tokens += used.prompt_tokens;
-            self.bill.total_tokens += used.total_tokens;
-            self.bill.query_count += 1;
-            self.bill.cost += query.response.cost(&query.model);
-            // self.bill.cache_retrievals
+    /// Refuse a request whose prompt and longest completion do not fit its model's context window, rather than pay for OpenAI to reject it, or that is not attributed to a user or could take anyone in `tags` past their budget
+    /// <br> A request let through has what it may cost at most reserved against the budgets of `tags` under `cache_key`, until its Query is recorded, see `.record()`, or it fails, see `.complete()`
+    fn preflight(&self, req: &ChatCompletionRequest, cache_key: &str, tags: &Tags) -> Result<()> {
+        let estimate = self.estimate(req)?;
+        if !estimate.fits() {
+            return Err(Error::ContextOverflow { model: req.model.clone(), tokens: estimate.prompt_tokens + estimate.max_completion_tokens, limit: estimate.context_window })
         }
+        let (budgets, ledger) = match (&self.budgets, &self.ledger) { (Some(budgets), Some(ledger)) => (budgets, ledger), _ => return Ok(()) };
+        if budgets.require_user && tags.get(Tags::USER).is_none() { return Err(Error::Untagged { tag: Tags::USER.to_string() }) }
+        self.spending.reserve(ledger, budgets, tags, cache_key, estimate.max_cost)
+    }
+
+    /// What `response` cost, in CENTS, at today's price of `model` in the account's `PricingTable`
+    fn cost_of(&self, response: &ChatCompletionResponse, model: GptModel) -> f32 {
+        self.pricing.cost_in_cents(model, &response.usage, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Record a completion served from the cache at `cache_key`, along with what it would have cost
+    fn record_cache_hit(&self, cache_key: &str, query: &Query) -> Result<()> {
+        self.record(LedgerEvent::Query(LedgerEntry::new(cache_key, query, true)))
+    }
 
-        // Save the state of self.bill to file
-        let bill = match fs::OpenOptions::new().create(true).truncate(true).write(true).open(BILL_FILEPATH) {Ok(f)=>f, Err(e)=>panic!("Could not update bill at {BILL_FILEPATH}, due to error:  ❌  {e}")};
-        serde_json::to_writer_pretty(&bill, &self.bill).expect("Serialization of bill to bill file");
-    }
-
-    /// <br> Fields `completion_tokens`, `prompt_tokens`, `total_tokens`, `query_count`, `cost` are reset.
-    /// <br> Field cache_retrievals is left alone
-    pub fn reset_bill(&mut self) -> () {
-        self.bill.completion_tokens = 0;
-        self.bill.prompt_tokens = 0;
-        self.bill.total_tokens = 0;
-        self.bill.query_count = 0;
-        self.bill.cost = 0.00;
-        let bill = match fs::OpenOptions::new().create(true).truncate(true).write(true).open(BILL_FILEPATH) {Ok(f)=>f, Err(e)=>panic!("Could not reset bill at {BILL_FILEPATH}, due to error:  ❌  {e}")};
-        serde_json::to_writer_pretty(&bill, &self.bill).expect("Serialization of bill to bill file");
+    /// Record `query`, asked of OpenAI and cached at `cache_key`, in the ledger, and add its usage to the bill <br>
+    /// Call it as soon as the response is in, before `.cache_query()`, so what was paid for is on the bill even if caching then fails
+    /// # Errors
+    /// `Error::Io` if the ledger cannot be written
+    pub fn update_bill(&self, cache_key: &str, query: &Query) -> Result<()> {
+        self.record(LedgerEvent::Query(LedgerEntry::new(cache_key, query, false)))
+    }
+
+    /// Start the bill again from zero, every field included. The ledger keeps what came before the reset, so `Ledger::by_day()` and the like still count it
+    pub fn reset_bill(&self) -> Result<()> {
+        self.record(LedgerEvent::Reset { at: chrono::Utc::now().timestamp_millis() })?;
         println!("🧾 Bill reset");
+        Ok(())
     }
 
     pub fn show_bill(&self) {
+        let bill = self.get_bill();
         println!("\n");
         println!("🧾 Bill So Far");
-        println!("Queries: {}", self.bill.query_count);
-        println!("Total Tokens: {}", self.bill.total_tokens);
-        println!("Bill: ${:.2}", self.bill.cost / 100.0);
+        println!("Queries: {}", bill.query_count);
+        println!("Total Tokens: {}", bill.total_tokens);
+        println!("Bill: ${:.2}", bill.cost / 100.0);
         println!("\n");
     }
 
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    /// A clone of this account whose completions are attributed to `tags`, e.g. the user, library and job of a request. It shares everything else with this account, bill and ledger included
+    /// <br> `apply_battery_to_pdf` and `ask_about_pdf` add the pdf's title as the `Tags::DOCUMENT`, and battery completions add the `Tags::BATTERY`, unless `tags` sets them.
+    /// ```no_run
+    /// # use rust_openai::{Battery, OpenAIAccount};
+    /// # use rust_openai::models::Tags;
+    /// # async fn job(openai: &OpenAIAccount) -> rust_openai::Result<()> {
+    /// let job = openai.with_tags(Tags::new().user("42").library("lib-7").job("job-3"));
+    /// job.apply_battery_to_pdf("voynich".to_string(), Battery::CompleteVoynich, None, None).await?;
+    /// let spent = job.ledger().unwrap().by_tag(Tags::JOB, ..)?;
+    /// # Ok(()) }
+    /// ```
+    pub fn with_tags(&self, tags: Tags) -> OpenAIAccount {
+        OpenAIAccount { tags, ..self.clone() }
+    }
+
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.max_tokens
+    }
+
+    /// Cap every later completion at `max_tokens`, or lift the cap with `None`. Capped requests get keys of their own, so completions cached without the cap are not served for them
+    pub fn set_max_tokens(&mut self, max_tokens: Option<u32>) {
+        self.max_tokens = max_tokens;
+    }
+
+    pub fn budgets(&self) -> Option<&Budgets> {
+        self.budgets.as_ref()
+    }
+
+    /// Check later requests against `budgets`, or against nothing with `None`. Clones made before keep the old budgets
+    /// # Errors
+    /// `Error::Usage` if the account keeps no ledger to read spending from
+    pub fn set_budgets(&mut self, budgets: Option<Budgets>) -> Result<()> {
+        if budgets.is_some() && self.ledger.is_none() { return Err(Error::Usage("budgets need a ledger to read spending from".to_string())) }
+        self.budgets = budgets;
+        Ok(())
+    }
+
+    pub fn pricing(&self) -> &PricingTable {
+        &self.pricing
+    }
+
+    /// Price later Queries with `pricing`. Queries already made keep the cost they were given
+    pub fn set_pricing(&mut self, pricing: PricingTable) {
+        self.pricing = pricing;
+    }
+
+    /// Switch between asking OpenAI, recording, replaying and cache-only, see `Mode`
+    pub fn set_mode(&mut self, mode: Mode) {
+        println!("🎞️  Mode set to {mode:?}");
+        self.mode = mode;
+    }
+
+    /// Point this account at a different provider, e.g. `ApiConfig::azure(..)` or `ApiConfig::local("http://localhost:8000/v1")`
+    pub fn set_api_config(&mut self, api: ApiConfig) {
+        println!("🔌 Requests will be sent to: {}", api.base_url);
+        self.api = api;
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Replaces the rate limiter, so this account (and clones made from it afterwards) no longer share a window with previous clones
+    pub fn set_rate_limits(&mut self, limits: RateLimits) {
+        self.rate_limiter = RateLimiter::new(limits);
+    }
+
+    /// Rebuilds the account's HTTP client with new timeouts, proxy, user agent or root certificates. Clones made before keep the old client.
+    /// # Errors
+    /// See `HttpConfig::client()`
+    pub fn set_http_config(&mut self, http: HttpConfig) -> Result<()> {
+        self.client = http.client()?;
+        self.http = http;
+        Ok(())
+    }
+
     pub fn set_temperature(&mut self, temperature: f32) { 
         if self.temperature < temperature {println!("🌡️  Temperature raised to {temperature}")} else {println!("🌡️  Temperature lowered to {temperature}")}
         self.temperature = temperature; 
@@ -295,21 +756,25 @@ impl OpenAIAccount {
 impl OpenAIAccount {
 
     /// The fully fledged "parse me this pdf please" method. Applies a battery defined in `batteries.rs` to the PDF with the title provided, in the provided directory, saves the response to cache inside a Query stamped with the battery used. <br><br> Here we want the title passed in, so that it can be used for creating the key, and saving the pdf to the provided directory (or the `DEFAULT_PDF_DIR` const if None provided) under `{dir}{title}.pdf`. <br><br>`DEFAULT_PDF_DIR` can be found in `openai_for_rs::constants`
-    pub async fn apply_battery_to_pdf(&mut self, pdf_title: String, battery_type: Battery, model: Option<GptModel>, input_dir: Option<String>) -> Result<Query, String> {
+    pub async fn apply_battery_to_pdf(&self, pdf_title: String, battery_type: Battery, model: Option<GptModel>, input_dir: Option<String>) -> Result<Query> {
         println!("\n--🗳️");
-        let dir = match input_dir { None => DEFAULT_PDF_DIR.to_string(), Some(s) => s };
         let model = match model {Some(m) => m, None => self.model};
+        let tags = self.tags.clone().or_with(Tags::DOCUMENT, &pdf_title).or_with(Tags::BATTERY, battery_type.as_prompt_stamp());
         let battery_label = battery_type.as_prompt_stamp();
-        let path_to_pdf = if dir.ends_with("/") {format!("{dir}{pdf_title}.pdf")} else if dir.contains("\\") {format!("{dir}\\{pdf_title}.pdf")} else {format!("{dir}/{pdf_title}.pdf")};
-        let query_key = format!("{pdf_title} - {battery_label}"); 
+        let path_to_pdf = pdf_path(input_dir, &pdf_title);
+        // The key covers the battery and the pdf's bytes, so the text is only extracted on a cache miss
+        let mut req = ChatCompletionRequest::from_prompt(model, battery_type.to_prompt(String::new())?, self.temperature).with_max_tokens(self.max_tokens);
+        let query_key = request_key(&format!("{pdf_title} - {battery_label}"), &req, Some(&document_hash(&path_to_pdf)?))?;
         
-        let query = match self.check_cache(&query_key, QueryType::PdfCompletion) {
+        // Concurrent jobs applying the same battery to the same pdf wait here, then find this job's Query in the cache
+        let _flight = self.in_flight.acquire(&query_key).await;
+        let query = match self.check_cache(&query_key, QueryType::PdfCompletion).await? {
             // If found in cache, retrieve the query
             Some(query) => {
                 let mut query = query.clone();
                 query.from_cache = true; 
-                self.bill.cache_retrievals += 1; 
-                self.update_bill(None); 
+                query.tags = tags;
+                self.record_cache_hit(&query_key, &query)?;
                 println!("--[Cached Answer]--");
                 query
             },
@@ -318,60 +783,90 @@ impl OpenAIAccount {
                 let from_cache = false;
                 println!("--[Sending to GPT]--");
                 // Load the pdf from the provided file path, or else return to the caller a NotFoundError 
-                let pdf = lopdf::Document::load(path_to_pdf).map_err(|e| return e.to_string())?;
-                let mut doc = String::new();
-                for page in 1..=pdf.get_pages().len() {
-                    let content = pdf.extract_text(&[page as u32]).expect("parse");
-                    doc.push_str(&content);
-                }
-                
-                let req = ChatCompletionRequest {
-                    model: model.to_string(),
-                    messages: vec![
-                        ChatCompletionMessage {
-                            role: MessageRole::user,
-                            content: Some(battery_type.to_prompt(doc)),
-                            name: None,
-                            function_call: None,
-                        },
-                    ], functions: None, function_call: None, temperature: Some(self.temperature)
-                };
+                let doc = read_pdf_text(&path_to_pdf)?;
+                req.messages[0].content = Some(battery_type.to_prompt(doc)?);
 
                 let start_time = std::time::Instant::now();
-                let response = match self.send_completion_request(req).await {Ok(res) => res, Err(e) => return Err(e.to_string())};
+                let response = self.complete(req, &query_key, &tags).await?;
                 let process_time = start_time.elapsed().as_millis() as u64;
                 
                 println!("--[Completion received]--");
 
                 // Build Query from Response
-                let query = Query { prompt: battery_type.as_prompt_stamp(), response: response.clone(), model, process_time, query_type: QueryType::PdfCompletion, cost: response.cost(&model), temperature: self.temperature, from_cache };
-                self.cache_query(&query_key, &query); // Add Query to Cache
-                self.update_bill(Some(&query)); // Add data to Bill
-                println!("--[Bill now shows: ${:.2}]--", self.bill.cost / 100.0);
-                println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, (query.response.cost(&query.model)));
+                let query = Query { prompt: battery_type.as_prompt_stamp(), response: response.clone(), model, process_time, query_type: QueryType::PdfCompletion, cost: self.cost_of(&response, model), temperature: self.temperature, from_cache, cached_at: None, last_used: None, tags };
+                self.update_bill(&query_key, &query)?; // Add data to Bill
+                self.cache_query(&query_key, &query).await?; // Add Query to Cache
+                println!("--[Bill now shows: ${:.2}]--", self.bill().cost / 100.0);
+                println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, query.cost);
                 query
             },
         };
-        println!("--[Got from or created to cache ('./{CACHE_FILEPATH}') under key: \"{pdf_title} - {battery_label}\"]--");
+        println!("--[Got from or created to cache under key: \"{query_key}\"]--");
         println!("--");
         Ok(query)
     }
 
+    /// Streaming counterpart of `.apply_battery_to_pdf()`, for forwarding the completion to a UI while it is generated. Drain the returned `CompletionStream`, then pass it to `.finish_stream()` to cache and bill the Query under the same key.
+    pub async fn apply_battery_to_pdf_stream(&self, pdf_title: String, battery_type: Battery, model: Option<GptModel>, input_dir: Option<String>) -> Result<CompletionStream> {
+        let model = match model {Some(m) => m, None => self.model};
+        let tags = self.tags.clone().or_with(Tags::DOCUMENT, &pdf_title).or_with(Tags::BATTERY, battery_type.as_prompt_stamp());
+        let battery_label = battery_type.as_prompt_stamp();
+        let path_to_pdf = pdf_path(input_dir, &pdf_title);
+        let mut req = ChatCompletionRequest::from_prompt(model, battery_type.to_prompt(String::new())?, self.temperature).with_max_tokens(self.max_tokens);
+        let query_key = request_key(&format!("{pdf_title} - {battery_label}"), &req, Some(&document_hash(&path_to_pdf)?))?;
+
+        let flight = self.in_flight.acquire(&query_key).await;
+        if let Some(query) = self.check_cache(&query_key, QueryType::PdfCompletion).await? {
+            return Ok(CompletionStream::from_cache(query_key, Query { tags, ..query }))
+        }
+
+        println!("--[Streaming from GPT]--");
+        let doc = read_pdf_text(&path_to_pdf)?;
+        req.messages[0].content = Some(battery_type.to_prompt(doc)?);
+
+        let pending = PendingQuery { cache_key: query_key, prompt: battery_label, query_type: QueryType::PdfCompletion, model, temperature: self.temperature, tags, start_time: std::time::Instant::now() };
+        Ok(self.complete_stream(req, pending).await?.holding(flight))
+    }
+
+    /// What `.apply_battery_to_pdf()` would send and may cost, without sending anything or touching the bill. A completion already in the cache is estimated as `cached`
+    /// # Errors
+    /// `Error::Io` or `Error::Pdf` if the pdf cannot be read
+    pub async fn apply_battery_to_pdf_dry_run(&self, pdf_title: String, battery_type: Battery, model: Option<GptModel>, input_dir: Option<String>) -> Result<Estimate> {
+        let model = match model {Some(m) => m, None => self.model};
+        let battery_label = battery_type.as_prompt_stamp();
+        let path_to_pdf = pdf_path(input_dir, &pdf_title);
+        let mut req = ChatCompletionRequest::from_prompt(model, battery_type.to_prompt(String::new())?, self.temperature).with_max_tokens(self.max_tokens);
+        let query_key = request_key(&format!("{pdf_title} - {battery_label}"), &req, Some(&document_hash(&path_to_pdf)?))?;
+
+        req.messages[0].content = Some(battery_type.to_prompt(read_pdf_text(&path_to_pdf)?)?);
+        let mut estimate = self.estimate(&req)?;
+        let now = chrono::Utc::now().timestamp_millis();
+        if self.cache.get(&query_key).await?.is_some_and(|query| !self.cache_policy.is_expired(&query, now)) {
+            estimate.cached = true;
+            estimate.max_cost = 0.0;
+        }
+        println!("🧮 {}: {estimate}", key::label(&query_key));
+        Ok(estimate)
+    }
+
     /// Apply the provided prompt question to a pdf
-    pub async fn ask_about_pdf(&mut self, pdf_title: String, prompt: String, model: Option<GptModel>) -> Result<Query, Status> {
+    pub async fn ask_about_pdf(&self, pdf_title: String, prompt: String, model: Option<GptModel>) -> Result<Query> {
         println!("--");
         
         let model = match model {Some(m) => m, None => self.model};
+        let tags = self.tags.clone().or_with(Tags::DOCUMENT, &pdf_title);
         let prompt = prompt.to_lowercase().replace("\n", " ");
-        let path_to_pdf = format!("./pdfs/{pdf_title}.pdf");
-        let query_key = format!("{pdf_title}: {prompt}"); 
-        let query = match self.check_cache(&query_key, QueryType::PdfCompletion) {
+        let path_to_pdf = pdf_path(None, &pdf_title);
+        let req = ChatCompletionRequest::from_prompt(model, prompt.clone(), self.temperature).with_max_tokens(self.max_tokens);
+        let query_key = request_key(&format!("{pdf_title}: {prompt}"), &req, Some(&document_hash(&path_to_pdf)?))?;
+        let _flight = self.in_flight.acquire(&query_key).await;
+        let query = match self.check_cache(&query_key, QueryType::PdfCompletion).await? {
             // If found in cache, retrieve the query
             Some(query) => {
                 let mut query = query.clone(); 
                 query.from_cache = true;
-                self.bill.cache_retrievals += 1; 
-                self.update_bill(None); 
+                query.tags = tags;
+                self.record_cache_hit(&query_key, &query)?;
                 println!("--[Cached Answer]--");
                 query
             },
@@ -380,256 +875,253 @@ impl OpenAIAccount {
                 let from_cache = false;
                 println!("--[Sending to GPT]--");
                 // Load the pdf from the provided file path, or else return to the caller a NotFoundError 
-                let pdf = lopdf::Document::load(path_to_pdf).map_err(|_| {return Status::NotFoundError})?;
-                let mut doc = String::new();
-                for page in 1..=pdf.get_pages().len() {
-                    let content = pdf.extract_text(&[page as u32]).expect("parse");
-                    doc.push_str(&content);
-                }
-                let req = ChatCompletionRequest {
-                    model: model.to_string(),
-                    messages: vec![
-                        ChatCompletionMessage {
-                            role: MessageRole::user,
-                            content: Some(prompt.clone()),
-                            name: None,
-                            function_call: None,
-                        },
-                    ], functions: None, function_call: None, temperature: Some(self.temperature)
-                };
+                read_pdf_text(&path_to_pdf)?;
                 let start_time = std::time::Instant::now();
-                let response = match self.send_completion_request(req).await {Ok(res) => res, Err(e) => return Err(Status::Error(e.to_string()))};
+                let response = self.complete(req, &query_key, &tags).await?;
                 let process_time = start_time.elapsed().as_millis() as u64;
                 println!("--[Completion received]--");
                 // Build Query from Response
-                let query = Query { prompt: prompt.clone(), response: response.clone(), model, process_time, query_type: QueryType::PdfCompletion, cost: response.cost(&model), temperature: self.temperature, from_cache };
-                // Add Query to Cache
-                self.cache_query(&query_key, &query);
+                let query = Query { prompt: prompt.clone(), response: response.clone(), model, process_time, query_type: QueryType::PdfCompletion, cost: self.cost_of(&response, model), temperature: self.temperature, from_cache, cached_at: None, last_used: None, tags };
                 // Add data to Bill
-                self.update_bill(Some(&query));
+                self.update_bill(&query_key, &query)?;
+                // Add Query to Cache
+                self.cache_query(&query_key, &query).await?;
 
-                println!("--[Bill now shows: ${:.2}]--", self.bill.cost / 100.0);
-                println!("--[Took: {}ms, Cost: {:.4} cents]--", process_time, (query.response.cost(&query.model)));
+                println!("--[Bill now shows: ${:.2}]--", self.bill().cost / 100.0);
+                println!("--[Took: {}ms, Cost: {:.4} cents]--", process_time, query.cost);
                 query
             },
         };
-        println!("--[Got from or created to cache under key: \"{pdf_title} - {prompt}\"]--");
+        println!("--[Got from or created to cache under key: \"{query_key}\"]--");
         println!("--");
 
         Ok(query)
     }
 
-    /// Get a completion that runs the provided battery, using the responses in the current state of the local cache (the cache file should be in sync therewith). The key in cache for this query will be "{title} - {battery stamp}" <br>Only uses responses in Queries whose query_type is `QueryType::PdfCompletion`, ingoring `PromptCompletions` and `MetaCompletions`. <br><br>Sends in the response content of each query concatenated together in the end of the Battery. <br><br>Choose a battery that is intended to run a meta completion, not send a document. I recommend labeling these batteries with a non-semantic prefix "Met", such that Battery::MetaAnalysis is explicitly a battery to be used on meta-analysis pdfs, while Battery::MetAnalysis would be a meta-battery intended to run on a concatenation of responses on many documents. <br><br>Always overwrites previous meta Queries.
-    pub async fn meta_complete_cache(&mut self, title: String, battery_type: Battery, model: Option<GptModel>) -> Result<Query, Status> {
+    /// Get a completion that runs the provided battery, using the responses in the current state of the local cache (the cache file should be in sync therewith). The key in cache for this query will be "{title} - {battery stamp} [{digest of the request}]" <br>Only uses responses in Queries whose query_type is `QueryType::PdfCompletion`, ingoring `PromptCompletions` and `MetaCompletions`. <br><br>Sends in the response content of each query concatenated together in the end of the Battery, oldest cached first. <br><br>Choose a battery that is intended to run a meta completion, not send a document. I recommend labeling these batteries with a non-semantic prefix "Met", such that Battery::MetaAnalysis is explicitly a battery to be used on meta-analysis pdfs, while Battery::MetAnalysis would be a meta-battery intended to run on a concatenation of responses on many documents. <br><br>Overwrites a previous meta Query only if it was run with the same battery, model and temperature on the same responses.
+    pub async fn meta_complete_cache(&self, title: String, battery_type: Battery, model: Option<GptModel>) -> Result<Query> {
         
         println!("\n--🗳️  Meta Completion");
         
         let model = match model {Some(m) => m, None => self.model};
+        let tags = self.tags.clone().or_with(Tags::BATTERY, battery_type.as_prompt_stamp());
         let battery_label = battery_type.as_prompt_stamp();
-        let query_key = format!("{title} - {battery_label}");
         
         let query = {
                 let from_cache = false;
-                // Convert the cache's PdfCompletions into a list of responses
-                let mut build_input = String::new();
-                let mut iter = 0;
-                println!("--[Combining Essays:");
-                for (_cache_key, query) in &self.cache {
-                    if let QueryType::PdfCompletion = query.query_type {
-                        iter += 1;
-                        build_input.push_str(format!("\n\n{iter})\n").as_str());
-                        let content = query.response.choices[0].clone().message.content.expect("presence of content field in GPT-response");
-                        build_input.push_str(content.as_str());
-                    }
-                    //if iter == 3 {println!("--Current state of the input at 3:\n{build_input}");}
-                }
-                let input = build_input;
-                println!("\n--Essays combined and ready for meta-completion.]--");
-
+                let (req, query_key) = self.meta_request(&title, &battery_type, model).await?;
                 println!("--[Sending to GPT]--");
-                let req = ChatCompletionRequest {
-                    model: model.to_string(),
-                    messages: vec![
-                        ChatCompletionMessage {
-                            role: MessageRole::user,
-                            content: Some(battery_type.to_prompt(input)),
-                            name: None,
-                            function_call: None,
-                        },
-                    ], functions: None, function_call: None, temperature: Some(self.temperature)
-                };
 
                 let start_time = std::time::Instant::now();
-                let response = match self.send_completion_request(req).await {Ok(res) => res, Err(e) => return Err(Status::Error(e.to_string()))};
+                let response = self.complete(req, &query_key, &tags).await?;
                 let process_time = start_time.elapsed().as_millis() as u64;
                 
                 println!("--[Completion received]--");
 
                 // Build Query from Response
-                let query = Query { prompt: battery_type.as_prompt_stamp(), response: response.clone(), model, process_time, query_type: QueryType::MetaCompletion, cost: response.cost(&model), temperature: self.temperature, from_cache };
-                self.cache_query(&query_key, &query); // Add Query to Cache
-                self.update_bill(Some(&query)); // Add data to Bill
-                println!("--[Bill now shows: ${:.2}]--", self.bill.cost / 100.0);
-                println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, (query.response.cost(&query.model)));
+                let query = Query { prompt: battery_type.as_prompt_stamp(), response: response.clone(), model, process_time, query_type: QueryType::MetaCompletion, cost: self.cost_of(&response, model), temperature: self.temperature, from_cache, cached_at: None, last_used: None, tags };
+                self.update_bill(&query_key, &query)?; // Add data to Bill
+                self.cache_query(&query_key, &query).await?; // Add Query to Cache
+                println!("--[Bill now shows: ${:.2}]--", self.bill().cost / 100.0);
+                println!("--[Took: {}ms, Cost: ¢{:.4}]--", process_time, query.cost);
                 query
         };
 
-        println!("--[Created meta completion query to cache under key: \"{title} - {battery_label}\"]--");
+        println!("--[Created meta completion query to cache under key labelled: \"{title} - {battery_label}\"]--");
         println!("--");
         Ok(query)
 
     }
 
+    /// What `.meta_complete_cache()` would send and may cost, without sending anything or touching the bill
+    /// # Errors
+    /// `Error::Db` or `Error::Io` if the cache cannot be read
+    pub async fn meta_complete_cache_dry_run(&self, title: String, battery_type: Battery, model: Option<GptModel>) -> Result<Estimate> {
+        let model = match model {Some(m) => m, None => self.model};
+        let (req, query_key) = self.meta_request(&title, &battery_type, model).await?;
+        let estimate = self.estimate(&req)?;
+        println!("🧮 {}: {estimate}", key::label(&query_key));
+        Ok(estimate)
+    }
+
+    /// The request `.meta_complete_cache()` sends, holding the response of every PdfCompletion in the cache, and the key its Query is cached at <br>
+    /// The responses are numbered in the order they were cached, then by key, so the same cache always makes the same request, and hits the same key, whatever order the backend lists it in
+    async fn meta_request(&self, title: &str, battery_type: &Battery, model: GptModel) -> Result<(ChatCompletionRequest, String)> {
+        let battery_label = battery_type.as_prompt_stamp();
+        // Convert the cache's PdfCompletions into a list of responses
+        let mut build_input = String::new();
+        let mut iter = 0;
+        println!("--[Combining Essays:");
+        let mut entries = self.cache.iter().await?;
+        entries.sort_by(|(a_key, a), (b_key, b)| a.cached_at.cmp(&b.cached_at).then_with(|| a_key.cmp(b_key)));
+        for (_cache_key, query) in &entries {
+            if let QueryType::PdfCompletion = query.query_type {
+                iter += 1;
+                build_input.push_str(format!("\n\n{iter})\n").as_str());
+                let content = query.response.choices.first().and_then(|choice| choice.message.content.as_deref()).unwrap_or_default();
+                build_input.push_str(content);
+            }
+            //if iter == 3 {println!("--Current state of the input at 3:\n{build_input}");}
+        }
+        let input = build_input;
+        println!("\n--Essays combined and ready for meta-completion.]--");
+
+        let req = ChatCompletionRequest::from_prompt(model, battery_type.to_prompt(input)?, self.temperature).with_max_tokens(self.max_tokens);
+        let query_key = request_key(&format!("{title} - {battery_label}"), &req, None)?;
+        Ok((req, query_key))
+    }
+
     ///// Uses the provided model and battery, inserting into the battery a manually constructed input. This allows middle-processing, after Queries have been built up in cache, before sending their data for meta-analysis. <br>If you just want to run the battery on the current state of the cache, use `.meta_complete_cache()`
     //pub async fn meta_complete(&mut self, input: String, battery_type: Battery, model: Option<GptModel>) {}
 }
 
 
+/// The pdf titled `pdf_title` in `input_dir`, or DEFAULT_PDF_DIR. Every pdf method, and `cache::rekey()`, looks pdfs up here
+pub(crate) fn pdf_path(input_dir: Option<String>, pdf_title: &str) -> String {
+    let dir = match input_dir { None => DEFAULT_PDF_DIR.to_string(), Some(s) => s };
+    if dir.ends_with("/") {format!("{dir}{pdf_title}.pdf")} else if dir.contains("\\") {format!("{dir}\\{pdf_title}.pdf")} else {format!("{dir}/{pdf_title}.pdf")}
+}
+
+/// The message of the first choice of a Query's response, as appended to a `Conversation`
+fn reply_message(query: &Query) -> ChatCompletionMessage {
+    query.response.choices.first().map(|choice| choice.message.clone()).unwrap_or(ChatCompletionMessage {
+        role: MessageRole::assistant, content: None, name: None, function_call: None,
+    })
+}
+
+/// Concatenate the extracted text of every page of the pdf at `path`
+pub(crate) fn read_pdf_text(path: &str) -> Result<String> {
+    let pdf_error = |source| Error::Pdf { path: path.to_string(), source };
+    let pdf = lopdf::Document::load(path).map_err(pdf_error)?;
+    let mut doc = String::new();
+    for page in 1..=pdf.get_pages().len() {
+        let content = pdf.extract_text(&[page as u32]).map_err(pdf_error)?;
+        doc.push_str(&content);
+    }
+    Ok(doc)
+}
+
+
 use super::models::db::prelude::*;
 use db::query_cache::*;
-use sea_orm::ActiveValue;
-use std::result::Result;
-use std::error::Error as ErrorTrait;
+
+/// Connect to the database at the `DATABASE_URL` environment variable
+async fn connect_db() -> Result<DatabaseConnection> {
+    let url = dotenvy::var("DATABASE_URL").map_err(|_| Error::Env("DATABASE_URL".to_string()))?;
+    Ok(Database::connect(url).await?)
+}
 
 /// Methods for coordinating the current cache state and the DB
 impl OpenAIAccount {
 
-    // Any time an OpenAIAccount is initiated, it is synced with the current state of the local cache files (assigned to constants)
-    // The state of the cache in memory is updated, and saved to the cache file any time requests are made, due to the design of request methods.
-    // At times, it may be necessary to engage in CR(U)D to the database, with regard to the current cache state (i.e. both in memory and in file) 
+    // The cache backend is updated any time requests are made, due to the design of request methods.
+    // When the backend is not the database itself, it may be necessary to engage in CR(U)D to the database, with regard to the current cache state
     // There are no Update methods because our data has no reason to be changed from its original state.
 
-    /// Save current cache (the cache file & in-memory cache map which are synced) to the db, replacing those keys that already exist.
-    pub async fn db_insert_cache(&self) -> std::result::Result<(), Box<dyn ErrorTrait>> {
+    /// Save the current contents of the cache backend to the db, replacing those keys that already exist.
+    /// <br> Only needed when the account's backend is not already a `SeaOrmCache` over the same database.
+    pub async fn db_insert_cache(&self) -> Result<()> {
         println!("🗄️  Saving cache to database...");
         let mut overwritten = false;
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL")?).await?;
-        let mut models: Vec<ActiveModel> = vec![]; // initialize a vector
-        models.reserve(self.cache.len()); // (optional) prepare memory ahead for length of the cache
-        for (cache_key, query) in &self.cache {
+        let db = connect_db().await?;
+        let entries = self.cache.iter().await?;
+        let mut models: Vec<ActiveModel> = Vec::with_capacity(entries.len());
+        for (cache_key, query) in &entries {
             let query_key_hash = calculate_hash(cache_key);
-            let extant_at_id = QueryCache::find().filter(Column::QueryKeyHash.eq(&query_key_hash)).one(&db).await.expect("Database check for query");
+            let extant_at_id = QueryCache::find().filter(Column::QueryKeyHash.eq(&query_key_hash)).one(&db).await?;
             if let Some(model) = extant_at_id {
-                QueryCache::delete_by_id(model.rid).exec(&db).await.expect("success of deletion by id during db_insert_cache()");
+                QueryCache::delete_by_id(model.rid).exec(&db).await?;
                 println!("🗄️  Model overwritten at query key hash: {query_key_hash}"); 
                 overwritten = true;
-                let graveyard = std::fs::OpenOptions::new().create(true).append(true).open("graveyard.json").expect("access to graveyard file");
-                serde_json::to_writer_pretty(graveyard, &model).expect("Serialization of an overwritten model to the graveyard");
+                self.archive(&model.query_key, &model.to_query()?, ArchiveReason::DbSync)?;
                 
             }
-            let model = ActiveModel { 
-                timestamp: ActiveValue::Set(chrono::Local::now().format("%d/%m/%Y %H:%M:%S").to_string()), 
-                model: ActiveValue::Set(query.model.to_string()), 
-                temperature: ActiveValue::Set(query.temperature), 
-                prompt: ActiveValue::Set(query.prompt.to_string()),
-                query_key: ActiveValue::Set(cache_key.to_string()), 
-                prompt_tokens: ActiveValue::Set(query.response.usage.prompt_tokens), 
-                completion_tokens: ActiveValue::Set(query.response.usage.completion_tokens), 
-                total_tokens: ActiveValue::Set(query.response.usage.total_tokens), 
-                process_time: ActiveValue::Set(query.process_time as i32), 
-                response: ActiveValue::Set(serde_json::to_value(query.response.clone()).expect("conversion to JSON value of query.response")), 
-                cost: ActiveValue::Set(query.cost),
-                query_key_hash: ActiveValue::Set(query_key_hash), 
-                rid: ActiveValue::NotSet
-            };
+            let model = ActiveModel::from_query(cache_key, query)?;
             models.push(model)
         }
         let _res = QueryCache::insert_many(models).exec(&db).await?;
-        if overwritten {println!("🪦  Any overwritten models can be recovered in graveyard file.")};
+        if overwritten && self.history.is_some() {println!("📜 Any overwritten models can be recovered from the query history.")};
         println!("🗄️  Cache saved to database.");
         Ok(())
     }
 
     /// Insert the Query found at the provided cache_key from local cache into the database. <br> Returns the `rid` of the inserted query as Some if the provided cache_key has a corresponding value, or None if it does not.
-    pub async fn db_insert_query(&self, cache_key: String) -> Option<i32> {
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL").expect("database env var")).await.expect("database connection");
-        let query = match self.cache.get(&cache_key) {Some(s)=>s, None=> return None};
-        
-        let model = ActiveModel { 
-            timestamp: ActiveValue::Set(chrono::Local::now().format("%d/%m/%Y %H:%M:%S").to_string()), 
-            model: ActiveValue::Set(query.model.to_string()), 
-            temperature: ActiveValue::Set(query.temperature), 
-            prompt: ActiveValue::Set(query.prompt.to_string()),
-            query_key: ActiveValue::Set(cache_key.to_string()), 
-            prompt_tokens: ActiveValue::Set(query.response.usage.prompt_tokens), 
-            completion_tokens: ActiveValue::Set(query.response.usage.completion_tokens), 
-            total_tokens: ActiveValue::Set(query.response.usage.total_tokens), 
-            process_time: ActiveValue::Set(query.process_time as i32), 
-            response: ActiveValue::Set(serde_json::to_value(query.response.clone()).expect("conversion to JSON value of query.response")), 
-            cost: ActiveValue::Set(query.cost),
-            query_key_hash: ActiveValue::Set(calculate_hash(&cache_key)), 
-            rid: ActiveValue::NotSet
-        };
+    pub async fn db_insert_query(&self, cache_key: String) -> Result<Option<i32>> {
+        let db = connect_db().await?;
+        let query = match self.cache.get(&cache_key).await? {Some(s)=>s, None=> return Ok(None)};
+        let model = ActiveModel::from_query(&cache_key, &query)?;
 
-        let res = QueryCache::insert(model).exec(&db).await.expect("insertion of ActiveModel to db during .db_insert_query()");
+        let res = QueryCache::insert(model).exec(&db).await?;
 
         let id = res.last_insert_id;
         println!("🗄️  Inserted into database query \"{key}\"", key = cache_key);
-        Some(id)
+        Ok(Some(id))
     }
 
 
-    /// Find all in db, convert models to queries, insert queries into the local cache according to query_key, overwriting if `overwrite` is `true` or skipping if not, then overwrite the cache file with the new state of the cache.  Returns the previous state of the cache, before db addition.
-    pub async fn db_read_to_cache(&mut self, overwrite: bool) -> Result< HashMap<String,Query> , Box<dyn ErrorTrait> > {
+    /// Find all in db, convert models to queries, insert queries into the cache backend according to query_key, overwriting if `overwrite` is `true` or skipping if not.  Returns the previous state of the cache, before db addition.
+    /// <br> Only needed when the account's backend is not already a `SeaOrmCache` over the same database.
+    pub async fn db_read_to_cache(&self, overwrite: bool) -> Result< HashMap<String,Query> > {
         println!("🗄️  Reading database into cache...");
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL")?).await?;
-        let previous_state = self.cache.clone();
+        let db = connect_db().await?;
+        let previous_state: HashMap<String, Query> = self.cache.iter().await?.into_iter().collect();
         let models = QueryCache::find().all(&db).await?;
 
-        // ? Here we are repeating the code for .cache_query(), with some adjustments, mainly so that we only overwrite and save to file once
+        // ? Here we are repeating the code for .cache_query(), with some adjustments, mainly so that nothing is sent to the query history
         for model in models { 
-            let query = model.to_query();
+            let query = model.to_query()?;
             // Make the key uniform if it is a prompt completion
             let query_key = if let QueryType::PromptCompletion = query.query_type {model.query_key.to_lowercase().replace("\n", " ")} else {model.query_key.to_string()};
-            if !overwrite { match self.check_cache(&query_key, query.query_type) { Some(_) => continue, None => ()};  };
-            // Add to self.cache
-            self.cache.insert(query_key, query);
+            if !overwrite && self.check_cache(&query_key, query.query_type).await?.is_some() { continue }
+            self.cache.put(&query_key, &query).await?;
         }
 
-        // Save the state of self.cache to file
-        let cache = match fs::OpenOptions::new().create(true).write(true).open(CACHE_FILEPATH) {Ok(f)=>f, Err(e)=>panic!("Could not cache query at {CACHE_FILEPATH}, due to error:  ❌  {e}")};
-        serde_json::to_writer_pretty(&cache, &self.cache).expect("Serialization of cache to cache file during db_read_to_cache");
-
         println!("🗄️  Database added to cache.");
         Ok(previous_state)
     }
 
+    /// Recompute the `query_key_hash` of every row of the database at `DATABASE_URL`, reporting rows that collide. See `SeaOrmCache::rehash()`
+    pub async fn db_rehash_keys(&self) -> Result<RehashReport> {
+        let db = connect_db().await?;
+        SeaOrmCache::new(db).rehash().await
+    }
+
     /// Returns Some(Model) if a row is found with the given key, else None.
-    pub async fn db_read_one_by_cache_key(&self, cache_key: String) -> Option<Model> {
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL").expect("Database env var")).await.expect("Database connection");
-        let model = QueryCache::find().filter(Column::QueryKey.eq(cache_key)).one(&db).await.expect("Database .find() call response success");
-        model
+    pub async fn db_read_one_by_cache_key(&self, cache_key: String) -> Result<Option<Model>> {
+        let db = connect_db().await?;
+        let model = QueryCache::find().filter(Column::QueryKey.eq(cache_key)).one(&db).await?;
+        Ok(model)
     }
 
-    pub async fn db_delete_one_by_id(&self, id: i32) -> Result<(), Box<dyn ErrorTrait>> {
+    pub async fn db_delete_one_by_id(&self, id: i32) -> Result<()> {
         println!("🗄️  Deleting by id: {id}");
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL")?).await?;
+        let db = connect_db().await?;
         let _res = QueryCache::delete_by_id(id).exec(&db).await?;
         Ok(())
     }
 
-    pub async fn db_delete_all(&self) -> Result<(), Box<dyn ErrorTrait>> {
+    pub async fn db_delete_all(&self) -> Result<()> {
         println!("🗄️  Delete database requested...");
         
         let mut line = String::new();
         println!("🗄️  Press Enter to continue...");
-        let _input = std::io::stdin().read_line(&mut line).expect("Failed to read line");
+        let _input = std::io::stdin().read_line(&mut line).map_err(Error::io("stdin"))?;
 
 
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL")?).await?;
+        let db = connect_db().await?;
         let _res = QueryCache::delete_many().exec(&db).await?;
         println!("🗄️  Database cleared.\n");
         Ok(())
     }
 
-    pub async fn db_read_all(&self) -> Result< HashMap<String,Query> , Box<dyn ErrorTrait> > {
+    pub async fn db_read_all(&self) -> Result< HashMap<String,Query> > {
         println!("🗄️  Read all from database requested...");
-        let db: DatabaseConnection = Database::connect(dotenvy::var("DATABASE_URL").expect("Database env var")).await.expect("Database connection");
+        let db = connect_db().await?;
 
         let mut db_cache: HashMap<String, Query> = HashMap::new();
         let models = QueryCache::find().all(&db).await?;
         for model in models {
-            db_cache.extend([ ( model.query_key.clone(), model.to_query())])
+            db_cache.extend([ ( model.query_key.clone(), model.to_query()?)])
         }
 
         Ok(db_cache)
@@ -638,42 +1130,137 @@ impl OpenAIAccount {
 }
 
 
-#[derive(Debug)]
-pub enum Status {
-    Success,
-    Error(String),
-    NotFoundError,
-    OpenAIError,
-    APIReachedLimit,
-
-}
-
 /// Machinery for the fundamental request-response process
 impl OpenAIAccount {
 
-    pub async fn send_completion_request(&self, req: ChatCompletionRequest) -> Result<ChatCompletionResponse, APIError> {
+    /// The completion of `req`, which missed the cache at `cache_key`, from wherever the account's `Mode` says. Requests sent to OpenAI are checked first, see `.preflight()`, and their reservation let go if they fail
+    async fn complete(&self, req: ChatCompletionRequest, cache_key: &str, tags: &Tags) -> Result<ChatCompletionResponse> {
+        if self.mode.is_online() { self.preflight(&req, cache_key, tags)?; }
+        let response = self.complete_unchecked(req, cache_key).await;
+        if response.is_err() { self.spending.release(cache_key) }
+        response
+    }
+
+    async fn complete_unchecked(&self, req: ChatCompletionRequest, cache_key: &str) -> Result<ChatCompletionResponse> {
+        match &self.mode {
+            Mode::Live => self.send_completion_request(req).await,
+            Mode::CacheOnly => Err(Error::CacheMiss { key: cache_key.to_string() }),
+            Mode::Record(path) => {
+                let response = self.send_completion_request(req.clone()).await?;
+                Fixtures::new(path).record(&req, &response)?;
+                println!("🎞️  Recorded the response to {} in {}", key::label(cache_key), path.display());
+                Ok(response)
+            },
+            Mode::Replay(path) => {
+                let response = Fixtures::new(path).replay(&req)?;
+                let response = response.ok_or_else(|| Error::NotRecorded { key: cache_key.to_string(), path: path.clone() })?;
+                println!("🎞️  Replayed the response to {} from {}", key::label(cache_key), path.display());
+                Ok(response)
+            },
+        }
+    }
+
+    /// Streaming counterpart of `.complete()`. In `Mode::Record`, the response is recorded by `.finish_stream()`
+    async fn complete_stream(&self, req: ChatCompletionRequest, pending: PendingQuery) -> Result<CompletionStream> {
+        if self.mode.is_online() { self.preflight(&req, &pending.cache_key, &pending.tags)?; }
+        let cache_key = pending.cache_key.clone();
+        let stream = self.complete_stream_unchecked(req, pending).await;
+        if stream.is_err() { self.spending.release(&cache_key) }
+        stream
+    }
+
+    async fn complete_stream_unchecked(&self, req: ChatCompletionRequest, pending: PendingQuery) -> Result<CompletionStream> {
+        match &self.mode {
+            Mode::Live => self.send_completion_request_stream(req, pending).await,
+            Mode::CacheOnly => Err(Error::CacheMiss { key: pending.cache_key }),
+            Mode::Record(_) => Ok(self.send_completion_request_stream(req.clone(), pending).await?.recording(req)),
+            Mode::Replay(path) => match Fixtures::new(path).replay(&req)? {
+                Some(response) => Ok(CompletionStream::from_fixture(response, pending)),
+                None => Err(Error::NotRecorded { key: pending.cache_key, path: path.clone() }),
+            },
+        }
+    }
+
+    pub async fn send_completion_request(&self, req: ChatCompletionRequest) -> Result<ChatCompletionResponse> {
+        let res = self.post("/chat/completions", &req).await?;
+        let r = res.json::<ChatCompletionResponse>().await?;
+        Ok(r)
+    }
+
+    /// Sends the request with `stream: true`, and returns a `CompletionStream` over the server-sent events, carrying `pending` so the finished stream can be turned into a Query.
+    /// <br> Usage reporting is requested with `stream_options`, so the finished stream can be billed.
+    pub(crate) async fn send_completion_request_stream(&self, mut req: ChatCompletionRequest, pending: PendingQuery) -> Result<CompletionStream> {
+        req.stream = Some(true);
+        req.stream_options = Some(StreamOptions { include_usage: true });
         let res = self.post("/chat/completions", &req).await?;
-        let r = res.json::<ChatCompletionResponse>().await;
-        match r { Ok(r) => Ok(r), Err(e) => Err(self.new_error(e)) }
-    }
-
-    fn new_error(&self, err: reqwest::Error) -> APIError {
-        APIError { message: err.to_string() }
-    }
-
-    pub async fn post<T: serde::ser::Serialize>(&self, path: &str, params: &T) -> Result<Response, APIError> {
-        let client = reqwest::Client::new();
-        let url = format!("{API_URL_V1}{path}");
-        let res = client
-            .post(&url)
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .header(reqwest::header::AUTHORIZATION, "Bearer ".to_owned() + &self.api_key)
-            .json(&params)
-            .send()
-            .await;
-        match res {
-            Ok(res) => match res.status().is_success() { true => Ok(res), false => Err(APIError { message: format!(  "{}: {}", res.status(), res.text().await.unwrap()   ) })  }, 
-            Err(e) => Err(self.new_error(e)),
+        Ok(CompletionStream::new(res, pending, self.http.read_timeout))
+    }
+
+    /// Sends `params` as JSON to the endpoint `path`, waiting on the client-side `RateLimiter` first.
+    /// <br> 429s, 5xxs, timeouts and dropped connections are retried according to the account's `RetryPolicy`, honouring `Retry-After` and the `x-ratelimit-*` headers. A 429 caused by an exhausted quota (`insufficient_quota`) is not retried, since waiting will not fix it.
+    /// <br> When the retries run out, the error is `Error::RateLimited` or `Error::ServerError`; other failures are returned at once as `Error::Api` (with OpenAI's error body parsed) or `Error::Http`.
+    pub async fn post<T: serde::ser::Serialize>(&self, path: &str, params: &T) -> Result<Response> {
+        let url = self.api.url(path);
+        let body = serde_json::to_vec(params).map_err(Error::json("the request body"))?;
+        // Rough estimate of ~4 bytes of JSON per token, used only for the tokens-per-minute window
+        let estimated_tokens = (body.len() / 4) as u32;
+
+        let mut attempt: u32 = 0;
+        loop {
+            self.rate_limiter.acquire(estimated_tokens).await;
+
+            let send = self.api.authorize(self.client.post(&url), &self.api_key)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .send();
+            // reqwest has no read timeout of its own, so the wait for OpenAI's answer is bounded here
+            let res = match self.http.read_timeout {
+                Some(read_timeout) => match tokio::time::timeout(read_timeout, send).await {
+                    Ok(res) => res.map_err(Error::Http),
+                    Err(_) => Err(Error::ServerError { status: None, attempts: attempt + 1, message: format!("no response within the read timeout of {}s", read_timeout.as_secs()) }),
+                },
+                None => send.await.map_err(Error::Http),
+            };
+
+            let (retry_after, error) = match res {
+                Ok(res) if res.status().is_success() => {
+                    self.rate_limiter.observe(res.headers());
+                    return Ok(res)
+                },
+                Ok(res) => {
+                    let status = res.status();
+                    let headers = res.headers().clone();
+                    self.rate_limiter.observe(&headers);
+                    let text = res.text().await.unwrap_or_default();
+                    let message = format!("{status}: {text}");
+                    let body = OpenAIErrorBody::parse(&text);
+                    let code = status.as_u16();
+
+                    let insufficient_quota = body.as_ref().and_then(|b| b.code.as_deref()) == Some("insufficient_quota");
+                    let rate_limited = code == 429 && !insufficient_quota;
+                    if !(rate_limited || status.is_server_error()) {
+                        return Err(Error::Api { status: code, message, body })
+                    }
+                    let retry_after = rate_limit::retry_after(&headers);
+                    let error = if rate_limited { Error::RateLimited { attempts: attempt + 1, message, body } } else { Error::ServerError { status: Some(code), attempts: attempt + 1, message } };
+                    (retry_after, error)
+                },
+                Err(Error::Http(e)) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    (None, Error::ServerError { status: None, attempts: attempt + 1, message: e.to_string() })
+                },
+                Err(error @ Error::ServerError { .. }) => (None, error),
+                Err(e) => return Err(e),
+            };
+
+            if attempt >= self.retry.max_retries {
+                println!("❌ Giving up after {} attempts: {error}", attempt + 1);
+                return Err(error)
+            }
+            let wait = self.retry.delay(attempt, retry_after);
+            if let Error::RateLimited { .. } = error { self.rate_limiter.pause_for(wait) }
+            println!("🔁 Retrying in {:.1}s (attempt {} of {}) after: {error}", wait.as_secs_f32(), attempt + 2, self.retry.max_retries + 1);
+            tokio::time::sleep(wait).await;
+            attempt += 1;
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+use serde::{Serialize, Deserialize};
+
+use crate::models::req_and_res::Usage;
+use crate::models::*;
+
+
+/// A multi-turn chat: an optional system prompt, followed by the user and assistant messages exchanged so far. <br>
+/// Pass it to `OpenAIAccount::converse` along with the next user message; the assistant's reply is appended automatically, so a follow-up question about a paper only needs the question itself.
+/// <br> Each turn is cached under `"{title} - Conversation [{digest of the request}]"`, so replaying the same conversation is served from cache, and the Queries of every turn are kept in `queries` so the conversation can be billed as a unit with `.cost()` and `.usage()`.
+/// ```
+/// # use rust_openai::Conversation;
+/// let conversation = Conversation::new("Cinnamon follow-up")
+///     .with_system_prompt("You are a careful reviewer of nutrition research.");
+/// assert_eq!(conversation.system_prompt(), Some("You are a careful reviewer of nutrition research."));
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conversation {
+    /// Label used in the cache keys of this conversation's turns
+    pub title: String,
+    /// The full history sent with each turn, starting with the system prompt if there is one
+    pub messages: Vec<ChatCompletionMessage>,
+    /// Model to use for every turn. `None` uses the model of the `OpenAIAccount`
+    pub model: Option<GptModel>,
+    /// The Query of each turn so far, in order
+    pub queries: Vec<Query>,
+}
+
+impl Conversation {
+
+    pub fn new(title: &str) -> Conversation {
+        Conversation { title: title.to_string(), messages: vec![], model: None, queries: vec![] }
+    }
+
+    pub fn with_system_prompt(mut self, prompt: &str) -> Conversation {
+        self.set_system_prompt(prompt);
+        self
+    }
+
+    pub fn with_model(mut self, model: GptModel) -> Conversation {
+        self.model = Some(model);
+        self
+    }
+
+    /// Sets the system prompt, replacing the existing one if any. It is always kept as the first message.
+    pub fn set_system_prompt(&mut self, prompt: &str) {
+        let message = ChatCompletionMessage { role: MessageRole::system, content: Some(prompt.to_string()), name: None, function_call: None };
+        match self.messages.first() {
+            Some(ChatCompletionMessage { role: MessageRole::system, .. }) => self.messages[0] = message,
+            _ => self.messages.insert(0, message),
+        }
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        match self.messages.first() {
+            Some(ChatCompletionMessage { role: MessageRole::system, content, .. }) => content.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Append a message to the history without sending anything, e.g. to seed the conversation with an earlier exchange
+    pub fn push(&mut self, role: MessageRole, content: &str) {
+        self.messages.push(ChatCompletionMessage { role, content: Some(content.to_string()), name: None, function_call: None });
+    }
+
+    /// The content of the latest assistant message
+    pub fn last_reply(&self) -> Option<&str> {
+        self.messages.iter().rev()
+            .find(|message| matches!(message.role, MessageRole::assistant))
+            .and_then(|message| message.content.as_deref())
+    }
+
+    /// The readable label of this conversation's cache keys, which `OpenAIAccount::converse` follows with the digest of the whole request (history, model, temperature and functions)
+    pub fn cache_label(&self) -> String {
+        format!("{} - Conversation", self.title)
+    }
+
+    /// Total cost in CENTS of the turns that were sent to OpenAI (turns served from cache cost nothing)
+    pub fn cost(&self) -> f32 {
+        self.queries.iter().filter(|query| !query.from_cache).map(|query| query.cost).sum()
+    }
+
+    /// Total token usage of the turns that were sent to OpenAI
+    pub fn usage(&self) -> Usage {
+        self.queries.iter().filter(|query| !query.from_cache).fold(
+            Usage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 },
+            |total, query| Usage {
+                prompt_tokens: total.prompt_tokens + query.response.usage.prompt_tokens,
+                completion_tokens: total.completion_tokens + query.response.usage.completion_tokens,
+                total_tokens: total.total_tokens + query.response.usage.total_tokens,
+            }
+        )
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::models::api_error::OpenAIErrorBody;
+
+
+/// Every failure the crate can return. All public methods of `OpenAIAccount` return `rust_openai::Result`, so a failing request, an unwritable cache file or an unreachable database is reported to the caller instead of panicking.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The request could not be sent, or its response could not be read
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// OpenAI answered with a status that retrying will not fix (invalid request, invalid key, context length exceeded, exhausted quota...)
+    #[error("OpenAI answered {status}: {}", body.as_ref().map(|b| b.message.as_str()).unwrap_or(message.as_str()))]
+    Api { status: u16, message: String, body: Option<OpenAIErrorBody> },
+
+    /// OpenAI kept answering with 429 until the `RetryPolicy` ran out
+    #[error("Rate limited by OpenAI after {attempts} attempts: {message}")]
+    RateLimited { attempts: u32, message: String, body: Option<OpenAIErrorBody> },
+
+    /// OpenAI kept answering with a 5xx, timing out, or dropping the connection until the `RetryPolicy` ran out
+    #[error("OpenAI server error after {attempts} attempts: {message}")]
+    ServerError { status: Option<u16>, attempts: u32, message: String },
+
+    /// A completion stream broke off, or sent a chunk that could not be parsed
+    #[error("Completion stream failed: {0}")]
+    Stream(String),
+
+    #[error("Could not read pdf at {path}: {source}")]
+    Pdf { path: String, #[source] source: lopdf::Error },
+
+    /// Reading or writing a cache, bill, history or battery file failed
+    #[error("IO error at {}: {source}", path.display())]
+    Io { path: PathBuf, #[source] source: std::io::Error },
+
+    #[error("Database error: {0}")]
+    Db(#[from] sea_orm::DbErr),
+
+    /// A cache or bill file could not be parsed. It is left untouched rather than replaced by an empty one, so the paid-for completions it holds are not lost
+    #[error("{} is corrupt ({message}). Restore it from its .bak file, or move it aside to start afresh", path.display())]
+    Corrupt { path: PathBuf, message: String },
+
+    /// A cache file, database row or response body did not have the expected shape
+    #[error("Could not (de)serialize {context}: {source}")]
+    Json { context: String, #[source] source: serde_json::Error },
+
+    #[error("Environment variable {0} is not set")]
+    Env(String),
+
+    #[error("Unknown model string: {0}")]
+    UnknownModel(String),
+
+    #[error("Unknown battery: {0}")]
+    UnknownBattery(String),
+
+    /// The account is in `Mode::CacheOnly`, and the completion is not in its cache
+    #[error("Not in the cache, and the account is cache-only: {key}")]
+    CacheMiss { key: String },
+
+    /// The account is in `Mode::Replay`, and the request is not in its fixture file
+    #[error("No recorded response in {} for: {key}", path.display())]
+    NotRecorded { key: String, path: PathBuf },
+
+    /// The request, counted locally with its `max_tokens`, does not fit its model's context window, so it was not sent. See `OpenAIAccount::estimate()`
+    #[error("{tokens} tokens of prompt and completion do not fit the {limit}-token context window of {model}")]
+    ContextOverflow { model: String, tokens: usize, limit: usize },
+
+    /// The request could take a user, library or job past its limit in `Budgets`, so it was not sent. Amounts are in CENTS
+    #[error("{tag} {value} has spent ¢{spent:.2} of its ¢{limit:.2} {period}, ¢{reserved:.2} is held for requests in flight, and the request may cost up to ¢{estimate:.2} more")]
+    BudgetExceeded { tag: String, value: String, period: String, limit: f32, spent: f32, reserved: f32, estimate: f32 },
+
+    /// The account has `Budgets`, but the request is not attributed to anyone they could hold to a limit, so it was not sent. Attribute it with `OpenAIAccount::with_tags()`
+    #[error("the request has no {tag} tag, so no budget can hold it to a limit")]
+    Untagged { tag: String },
+
+    /// Misuse of the api, such as finishing a stream before draining it
+    #[error("{0}")]
+    Usage(String),
+}
+
+impl Error {
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Error {
+        let path = path.into();
+        move |source| Error::Io { path, source }
+    }
+
+    pub(crate) fn json(context: impl Into<String>) -> impl FnOnce(serde_json::Error) -> Error {
+        let context = context.into();
+        move |source| Error::Json { context, source }
+    }
+
+    /// The parsed OpenAI error body, when the failure came from the api
+    pub fn openai_error(&self) -> Option<&OpenAIErrorBody> {
+        match self {
+            Error::Api { body, .. } | Error::RateLimited { body, .. } => body.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the failure is a rate limit or server error that outlasted every retry, so running the job again later may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::RateLimited { .. } | Error::ServerError { .. })
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
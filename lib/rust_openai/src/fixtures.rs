@@ -0,0 +1,90 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::cache::key::request_digest;
+use crate::error::{Error, Result};
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
+use crate::persist::{self, FileLock};
+
+
+/// Request/response pairs recorded by an account in `Mode::Record`, and served back in `Mode::Replay`. Kept as an append-only JSONL file with one `Fixture` per line. <br>
+/// Requests are matched by `request_digest()`, so a replayed request must match the recorded one in model, messages, temperature and functions, whether or not it is streamed. When a request was recorded more than once, the last recording is served.
+#[derive(Clone, Debug)]
+pub struct Fixtures {
+    path: PathBuf,
+}
+
+/// One recorded completion
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fixture {
+    /// `request_digest()` of `request`
+    pub digest: String,
+    /// The request as sent, for reading the fixture file
+    pub request: Value,
+    pub response: ChatCompletionResponse,
+}
+
+impl Fixtures {
+
+    /// The fixtures in the file at `path`, which is created on the first recording
+    pub fn new(path: impl Into<PathBuf>) -> Fixtures {
+        Fixtures { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `req` and the `response` OpenAI gave to it. A last line left torn by a crash is dropped first, so it never runs into the new one
+    /// ```
+    /// # use rust_openai::fixtures::Fixtures;
+    /// # use rust_openai::GptModel;
+    /// # use rust_openai::models::{ChatCompletionRequest, ChatCompletionResponse};
+    /// # use std::io::Write;
+    /// # let response: ChatCompletionResponse = serde_json::from_str(r#"{"id":"","object":"","created":0,"model":"","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":0,"total_tokens":0}}"#).unwrap();
+    /// let path = std::env::temp_dir().join("fixtures-crash-tail.jsonl");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let fixtures = Fixtures::new(&path);
+    /// let req = ChatCompletionRequest::from_prompt(GptModel::Gpt4, "hello".to_string(), 0.0);
+    /// fixtures.record(&req, &response).unwrap();
+    /// // A crash in the middle of the next line
+    /// std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(br#"{"digest":"ab"#).unwrap();
+    /// fixtures.record(&req, &response).unwrap();
+    /// assert_eq!(fixtures.all().unwrap().len(), 2);
+    /// ```
+    /// # Errors
+    /// `Error::Io` if the fixture file cannot be written
+    pub fn record(&self, req: &ChatCompletionRequest, response: &ChatCompletionResponse) -> Result<()> {
+        let fixture = Fixture {
+            digest: request_digest(req, None)?,
+            request: serde_json::to_value(req).map_err(Error::json("a request to the fixtures"))?,
+            response: response.clone(),
+        };
+        let mut line = serde_json::to_vec(&fixture).map_err(Error::json("a fixture"))?;
+        line.push(b'\n');
+        let _lock = FileLock::exclusive(&self.path)?;
+        persist::repair_tail::<Fixture>(&self.path)?;
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).map_err(Error::io(&self.path))?;
+        file.write_all(&line).map_err(Error::io(&self.path))?;
+        file.sync_data().map_err(Error::io(&self.path))
+    }
+
+    /// The recorded response to `req`, if it was recorded
+    /// # Errors
+    /// `Error::Io` if the fixture file cannot be read, and `Error::Corrupt` if a line other than a torn last one cannot be parsed
+    pub fn replay(&self, req: &ChatCompletionRequest) -> Result<Option<ChatCompletionResponse>> {
+        let digest = request_digest(req, None)?;
+        Ok(self.all()?.into_iter().rev().find(|fixture| fixture.digest == digest).map(|fixture| fixture.response))
+    }
+
+    /// Every recorded fixture, oldest first. A last line without its newline, cut short by a crash, is skipped; any other line that cannot be parsed fails the read
+    /// # Errors
+    /// `Error::Io` if the fixture file cannot be read, and `Error::Corrupt` if a line other than a torn last one cannot be parsed
+    pub fn all(&self) -> Result<Vec<Fixture>> {
+        persist::read_jsonl(&self.path)
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as KeyLock, OwnedMutexGuard};
+
+
+/// Single-flight of requests by cache key, shared between clones of an `OpenAIAccount`. <br>
+/// A completion holds its key from the cache check until its Query is cached. Concurrent callers with the same key wait for it, then find its Query in the cache, so OpenAI is asked and billed once.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InFlight {
+    keys: Arc<Mutex<HashMap<String, Arc<KeyLock<()>>>>>,
+}
+
+/// Holds a cache key in flight until dropped
+#[derive(Debug)]
+pub(crate) struct FlightGuard {
+    key: String,
+    flights: InFlight,
+    _held: OwnedMutexGuard<()>,
+}
+
+impl InFlight {
+
+    /// Wait until no other caller holds `key`, then hold it until the returned guard is dropped
+    pub(crate) async fn acquire(&self, key: &str) -> FlightGuard {
+        let lock = self.keys().entry(key.to_string()).or_default().clone();
+        let held = match lock.clone().try_lock_owned() {
+            Ok(held) => held,
+            Err(_) => {
+                println!("🛫 Waiting for the same request, already in flight");
+                lock.lock_owned().await
+            },
+        };
+        FlightGuard { key: key.to_string(), flights: self.clone(), _held: held }
+    }
+
+    fn keys(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<KeyLock<()>>>> {
+        self.keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        let mut keys = self.flights.keys();
+        // Only this guard and the map still refer to the lock, so nobody is waiting for the key
+        if keys.get(&self.key).is_some_and(|lock| Arc::strong_count(lock) <= 2) {
+            keys.remove(&self.key);
+        }
+    }
+}
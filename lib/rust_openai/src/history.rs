@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::models::{Query, QueryType};
+use crate::models::db::query_cache::Model;
+use crate::persist::{self, FileLock};
+
+
+/// Every Query that left an account's cache, kept as an append-only JSONL file with one `Version` per line. This replaces the old `graveyard.json`, which was not valid JSON and did not record keys. <br>
+/// Versions are numbered by their position in the file, so an `id` never changes. List the versions of a key with `.versions()`, compare two with `diff()`, and put one back into the cache with `OpenAIAccount::restore_version`.
+/// ```no_run
+/// # use rust_openai::history::QueryHistory;
+/// let history = QueryHistory::new("history.jsonl");
+/// for version in history.versions("spell alphabet [...]").unwrap() {
+///     println!("#{} {:?} at {}", version.id, version.reason, version.archived_at);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct QueryHistory {
+    path: PathBuf,
+}
+
+/// A past value of a cache entry
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Version {
+    /// Position of this version in the history file, counting from 0
+    #[serde(skip)]
+    pub id: usize,
+    /// The cache key the Query was stored at
+    pub key: String,
+    /// Unix time in milliseconds at which the Query left the cache
+    pub archived_at: i64,
+    pub reason: ArchiveReason,
+    pub query: Query,
+}
+
+/// Why a Query left the cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArchiveReason {
+    /// A new Query was cached at the same key
+    Overwrite,
+    /// The row was replaced in the database by `OpenAIAccount::db_insert_cache`
+    DbSync,
+    /// Dropped to keep the cache within the size limits of its `CachePolicy`
+    Eviction,
+    /// Dropped after outliving the TTL of its `CachePolicy`
+    Expiry,
+    /// Replaced by an older version with `OpenAIAccount::restore_version`
+    Restore,
+    /// Read from an old `graveyard.json` by `.import_graveyard()`
+    Import,
+}
+
+impl QueryHistory {
+
+    /// The history kept in the file at `path`, which is created on the first archived Query
+    pub fn new(path: impl Into<PathBuf>) -> QueryHistory {
+        QueryHistory { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `query`, which is leaving the cache at `key`, as a new version. The history file is locked while writing, so processes sharing it never interleave their lines, and a last line left torn by a crash is dropped first, so it never runs into the new one
+    /// ```
+    /// # use rust_openai::history::{ArchiveReason, QueryHistory};
+    /// # use rust_openai::Query;
+    /// # use std::io::Write;
+    /// # let query: Query = serde_json::from_str(r#"{"prompt":"p","cost":0.0,"response":{"id":"","object":"","created":0,"model":"","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":0,"total_tokens":0}},"process_time":0,"model":"Gpt4","query_type":"PromptCompletion","temperature":0.0,"from_cache":false}"#).unwrap();
+    /// let path = std::env::temp_dir().join("history-crash-tail.jsonl");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let history = QueryHistory::new(&path);
+    /// history.archive("p", &query, ArchiveReason::Overwrite).unwrap();
+    /// // A crash in the middle of the next line
+    /// std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(br#"{"key":"p","archived"#).unwrap();
+    /// history.archive("p", &query, ArchiveReason::Overwrite).unwrap();
+    /// assert_eq!(history.all().unwrap().len(), 2);
+    /// ```
+    /// # Errors
+    /// `Error::Io` if the history file cannot be written
+    pub fn archive(&self, key: &str, query: &Query, reason: ArchiveReason) -> Result<()> {
+        let version = Version { id: 0, key: key.to_string(), archived_at: chrono::Utc::now().timestamp_millis(), reason, query: query.clone() };
+        let mut line = serde_json::to_vec(&version).map_err(Error::json("a version to the history"))?;
+        line.push(b'\n');
+        let _lock = FileLock::exclusive(&self.path)?;
+        persist::repair_tail::<Version>(&self.path)?;
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).map_err(Error::io(&self.path))?;
+        file.write_all(&line).map_err(Error::io(&self.path))?;
+        file.sync_data().map_err(Error::io(&self.path))
+    }
+
+    /// Every version in the history, oldest first. A last line without its newline, cut short by a crash, is skipped; any other line that cannot be parsed fails the read
+    /// ```
+    /// # use rust_openai::history::{ArchiveReason, QueryHistory};
+    /// # use rust_openai::{Error, Query};
+    /// # use std::io::Write;
+    /// # let query: Query = serde_json::from_str(r#"{"prompt":"p","cost":0.0,"response":{"id":"","object":"","created":0,"model":"","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":0,"total_tokens":0}},"process_time":0,"model":"Gpt4","query_type":"PromptCompletion","temperature":0.0,"from_cache":false}"#).unwrap();
+    /// let path = std::env::temp_dir().join("history-corrupt-tail.jsonl");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let history = QueryHistory::new(&path);
+    /// history.archive("p", &query, ArchiveReason::Overwrite).unwrap();
+    /// std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(br#"{"key":"p","archived"#).unwrap();
+    /// assert_eq!(history.all().unwrap().len(), 1);
+    /// // The same line, but complete: not a crash, so it is not hidden
+    /// std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"\n").unwrap();
+    /// assert!(matches!(history.all(), Err(Error::Corrupt { .. })));
+    /// ```
+    /// # Errors
+    /// `Error::Io` if the file cannot be read, and `Error::Corrupt` if a line other than a torn last one cannot be parsed
+    pub fn all(&self) -> Result<Vec<Version>> {
+        let versions = persist::read_jsonl::<Version>(&self.path)?;
+        Ok(versions.into_iter().enumerate().map(|(id, version)| Version { id, ..version }).collect())
+    }
+
+    /// The versions of the entry at `key`, oldest first
+    pub fn versions(&self, key: &str) -> Result<Vec<Version>> {
+        Ok(self.all()?.into_iter().filter(|version| version.key == key).collect())
+    }
+
+    /// The version numbered `id`, if there is one
+    pub fn get(&self, id: usize) -> Result<Option<Version>> {
+        Ok(self.all()?.into_iter().find(|version| version.id == id))
+    }
+
+    /// Archive everything in the concatenated JSON objects of an old `graveyard.json`, with reason `Import`, returning how many Queries were found. <br>
+    /// Overwritten queries were buried without their key, so prompt completions are filed under their lowercased prompt and other Queries under their prompt or battery stamp. Database rows and evicted entries keep their key.
+    /// # Errors
+    /// `Error::Io` if either file cannot be read or written, and `Error::Corrupt` if the graveyard holds something other than JSON objects
+    pub fn import_graveyard(&self, graveyard: impl AsRef<Path>) -> Result<usize> {
+        let graveyard = graveyard.as_ref();
+        let text = fs::read_to_string(graveyard).map_err(Error::io(graveyard))?;
+        let mut imported = 0;
+        for value in serde_json::Deserializer::from_str(&text).into_iter::<Value>() {
+            let value = value.map_err(|e| Error::Corrupt { path: graveyard.to_path_buf(), message: e.to_string() })?;
+            let (key, query) = match buried_query(value) {
+                Some(found) => found,
+                None => { println!("🪦  Skipped a graveyard entry that is not a Query"); continue },
+            };
+            self.archive(&key, &query, ArchiveReason::Import)?;
+            imported += 1;
+        }
+        println!("📜 Imported {imported} queries from {} into {}", graveyard.display(), self.path.display());
+        Ok(imported)
+    }
+}
+
+/// The key and Query of one value buried in a graveyard: a bare Query, a `{"key", "query"}` pair, or a `query_cache` row
+fn buried_query(value: Value) -> Option<(String, Query)> {
+    if let (Some(key), Some(query)) = (value.get("key").and_then(Value::as_str), value.get("query")) {
+        return Some((key.to_string(), serde_json::from_value(query.clone()).ok()?))
+    }
+    if let Ok(row) = serde_json::from_value::<Model>(value.clone()) {
+        return Some((row.query_key.clone(), row.to_query().ok()?))
+    }
+    let query: Query = serde_json::from_value(value).ok()?;
+    let key = match query.query_type {
+        QueryType::PromptCompletion => query.prompt.to_lowercase().replace('\n', " "),
+        _ => query.prompt.clone(),
+    };
+    Some((key, query))
+}
+
+
+/// A field that differs between two Queries, addressed by its path in the Query's JSON, e.g. `response.choices[0].message.content`
+#[derive(Clone, Debug, Serialize)]
+pub struct Change {
+    pub path: String,
+    /// `None` if the field is only in the newer Query
+    pub before: Option<Value>,
+    /// `None` if the field is only in the older Query
+    pub after: Option<Value>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |value: &Option<Value>| value.as_ref().map_or("(none)".to_string(), Value::to_string);
+        write!(f, "{}: {} → {}", self.path, show(&self.before), show(&self.after))
+    }
+}
+
+/// The fields that differ between `before` and `after`, in path order. `from_cache` and `last_used` are bookkeeping and left out
+/// # Errors
+/// `Error::Json` if either Query cannot be serialized
+pub fn diff(before: &Query, after: &Query) -> Result<Vec<Change>> {
+    let flatten_query = |query: &Query| -> Result<BTreeMap<String, Value>> {
+        let mut fields = BTreeMap::new();
+        flatten(String::new(), serde_json::to_value(query).map_err(Error::json("a Query to diff"))?, &mut fields);
+        fields.remove("from_cache");
+        fields.remove("last_used");
+        Ok(fields)
+    };
+    let (mut before, mut after) = (flatten_query(before)?, flatten_query(after)?);
+
+    let mut paths: Vec<String> = before.keys().chain(after.keys()).cloned().collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths.into_iter().filter_map(|path| {
+        let (old, new) = (before.remove(&path), after.remove(&path));
+        (old != new).then_some(Change { path, before: old, after: new })
+    }).collect())
+}
+
+/// Collect the leaves of `value` into `fields`, keyed by their path
+fn flatten(path: String, value: Value, fields: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(object) => for (key, value) in object {
+            let path = if path.is_empty() { key } else { format!("{path}.{key}") };
+            flatten(path, value, fields)
+        },
+        Value::Array(array) => for (index, value) in array.into_iter().enumerate() {
+            flatten(format!("{path}[{index}]"), value, fields)
+        },
+        leaf => { fields.insert(path, leaf); },
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::error::{Error, Result};
+use crate::models::{Bill, GptModel, Query, QueryType, Tags};
+use crate::persist::{self, FileLock};
+
+
+/// Every billable event of an account, kept as an append-only JSONL file with one `LedgerEvent` per line. <br>
+/// Nothing in the ledger is ever rewritten: `Bill` is a view over it, counting what came after the last `Reset`, and spending can be broken down by day, month, model or tag over any span of time.
+/// Processes sharing the file lock it to append, so none of their events are lost.
+/// ```no_run
+/// # use rust_openai::ledger::Ledger;
+/// let ledger = Ledger::new("ledger.jsonl");
+/// let since = chrono::Utc::now().timestamp_millis() - 7 * 24 * 60 * 60 * 1000;
+/// for (day, bill) in ledger.by_day(since..).unwrap() {
+///     println!("{day}: ${:.2} over {} queries", bill.cost / 100.0, bill.query_count);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Ledger {
+    path: PathBuf,
+}
+
+/// One line of the ledger
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum LedgerEvent {
+    /// A completion asked of OpenAI, or served from the cache
+    Query(LedgerEntry),
+    /// Entries dropped from the cache by its `CachePolicy`
+    Eviction { at: i64, count: i32 },
+    /// `OpenAIAccount::reset_bill()` was called. The bill only counts what comes after it, while the events before it stay in the ledger
+    Reset { at: i64 },
+    /// The totals of a `bill.json` written before the ledger was kept, carried over by `.import_bill()`
+    Import { at: i64, from: PathBuf, bill: Bill },
+}
+
+/// A completion, with what it cost or, served from the cache, what it would have cost
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// Unix time in milliseconds
+    pub at: i64,
+    /// The cache key of the Query
+    pub key: String,
+    pub model: GptModel,
+    pub query_type: QueryType,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub total_tokens: i32,
+    /// In CENTS
+    pub cost: f32,
+    /// Served from the cache, so nothing was paid for it
+    pub cache_hit: bool,
+    /// What the completion is attributed to, see `Tags`
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+}
+
+impl LedgerEntry {
+
+    /// `query`, cached at `key`, as it happens now
+    pub(crate) fn new(key: &str, query: &Query, cache_hit: bool) -> LedgerEntry {
+        let usage = &query.response.usage;
+        LedgerEntry {
+            at: Utc::now().timestamp_millis(),
+            key: key.to_string(),
+            model: query.model,
+            query_type: query.query_type,
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+            cost: query.cost,
+            cache_hit,
+            tags: query.tags.clone(),
+        }
+    }
+}
+
+/// What `Ledger::tail()` read past an offset
+#[derive(Clone, Debug)]
+pub(crate) struct LedgerTail {
+    pub(crate) entries: Vec<LedgerEntry>,
+    /// Offset just past the last complete line, to read on from next time
+    pub(crate) end: u64,
+    /// The ledger is shorter than the offset, e.g. it was replaced, so it was read from the start
+    pub(crate) restarted: bool,
+}
+
+impl LedgerEvent {
+
+    /// Unix time in milliseconds at which the event happened
+    pub fn at(&self) -> i64 {
+        match self {
+            LedgerEvent::Query(entry) => entry.at,
+            LedgerEvent::Eviction { at, .. } | LedgerEvent::Reset { at } | LedgerEvent::Import { at, .. } => *at,
+        }
+    }
+}
+
+impl Ledger {
+
+    /// The ledger kept in the file at `path`, which is created on the first event
+    pub fn new(path: impl Into<PathBuf>) -> Ledger {
+        Ledger { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `event`. The ledger file is locked while writing, so processes sharing it never interleave their lines, and a last line left torn by a crash is dropped first, so it never runs into the new one
+    /// ```
+    /// # use rust_openai::ledger::{Ledger, LedgerEvent};
+    /// # use std::io::Write;
+    /// let path = std::env::temp_dir().join("ledger-crash-tail.jsonl");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let ledger = Ledger::new(&path);
+    /// ledger.record(&LedgerEvent::Reset { at: 0 }).unwrap();
+    /// // A crash in the middle of the next line
+    /// std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(br#"{"event":"Query","at":17"#).unwrap();
+    /// ledger.record(&LedgerEvent::Reset { at: 1 }).unwrap();
+    /// assert_eq!(ledger.events().unwrap().len(), 2);
+    /// ```
+    /// # Errors
+    /// `Error::Io` if the ledger file cannot be written
+    pub fn record(&self, event: &LedgerEvent) -> Result<()> {
+        let _lock = FileLock::exclusive(&self.path)?;
+        self.append(event)
+    }
+
+    /// Append `event`, under the exclusive lock
+    fn append(&self, event: &LedgerEvent) -> Result<()> {
+        persist::repair_tail::<LedgerEvent>(&self.path)?;
+        let mut line = serde_json::to_vec(event).map_err(Error::json("an event to the ledger"))?;
+        line.push(b'\n');
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).map_err(Error::io(&self.path))?;
+        file.write_all(&line).map_err(Error::io(&self.path))?;
+        file.sync_data().map_err(Error::io(&self.path))
+    }
+
+    /// Every event in the ledger, oldest first. A last line without its newline, cut short by a crash, is skipped; any other line that cannot be parsed fails the read
+    /// # Errors
+    /// `Error::Io` if the file cannot be read, and `Error::Corrupt` if a line other than a torn last one cannot be parsed
+    pub fn events(&self) -> Result<Vec<LedgerEvent>> {
+        let _lock = FileLock::shared(&self.path)?;
+        self.read()
+    }
+
+    fn read(&self) -> Result<Vec<LedgerEvent>> {
+        persist::read_jsonl(&self.path)
+    }
+
+    /// The Query entries on the lines after byte `offset`, for a reader that has read the ledger up to there, see `LedgerTail`
+    /// # Errors
+    /// `Error::Io` if the file cannot be read, and `Error::Corrupt` if a complete line cannot be parsed
+    pub(crate) fn tail(&self, offset: u64) -> Result<LedgerTail> {
+        let _lock = FileLock::shared(&self.path)?;
+        let mut file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LedgerTail { entries: Vec::new(), end: 0, restarted: offset > 0 }),
+            Err(e) => return Err(Error::Io { path: self.path.clone(), source: e }),
+        };
+        let len = file.metadata().map_err(Error::io(&self.path))?.len();
+        let restarted = len < offset;
+        let start = if restarted { 0 } else { offset };
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(start)).map_err(Error::io(&self.path))?;
+        file.read_to_end(&mut bytes).map_err(Error::io(&self.path))?;
+
+        // A last line without its newline is still being written, or was torn by a crash: leave it for the next read
+        let complete = bytes.iter().rposition(|byte| *byte == b'\n').map_or(0, |newline| newline + 1);
+        let mut entries = Vec::new();
+        let mut at = start;
+        for line in bytes[..complete].split_inclusive(|byte| *byte == b'\n') {
+            match serde_json::from_slice::<LedgerEvent>(line) {
+                Ok(LedgerEvent::Query(entry)) => entries.push(entry),
+                Ok(_) => (),
+                Err(e) => return Err(Error::Corrupt { path: self.path.clone(), message: format!("line at byte {at}: {e}") }),
+            }
+            at += line.len() as u64;
+        }
+        Ok(LedgerTail { entries, end: at, restarted })
+    }
+
+    /// The bill since the last reset, see `Bill::from_events()`
+    pub fn bill(&self) -> Result<Bill> {
+        Ok(Bill::from_events(&self.events()?))
+    }
+
+    /// The completions recorded during `span`, in unix milliseconds, e.g. `from..to` or `since..`, oldest first
+    pub fn entries(&self, span: impl RangeBounds<i64>) -> Result<Vec<LedgerEntry>> {
+        Ok(self.events()?.into_iter().filter_map(|event| match event {
+            LedgerEvent::Query(entry) if span.contains(&entry.at) => Some(entry),
+            _ => None,
+        }).collect())
+    }
+
+    /// What was spent during `span` on each day (UTC). Resets are ignored, and imported bills belong to no day
+    pub fn by_day(&self, span: impl RangeBounds<i64>) -> Result<BTreeMap<NaiveDate, Bill>> {
+        self.by_date(span, |day| day)
+    }
+
+    /// What was spent during `span` in each month (UTC), keyed by the first day of the month
+    pub fn by_month(&self, span: impl RangeBounds<i64>) -> Result<BTreeMap<NaiveDate, Bill>> {
+        self.by_date(span, |day| day.with_day(1).unwrap_or(day))
+    }
+
+    fn by_date(&self, span: impl RangeBounds<i64>, period: fn(NaiveDate) -> NaiveDate) -> Result<BTreeMap<NaiveDate, Bill>> {
+        let mut totals: BTreeMap<NaiveDate, Bill> = BTreeMap::new();
+        for event in self.events()? {
+            if matches!(event, LedgerEvent::Reset { .. } | LedgerEvent::Import { .. }) || !span.contains(&event.at()) { continue }
+            let day = Utc.timestamp_millis_opt(event.at()).single().map_or(NaiveDate::MIN, |at| at.date_naive());
+            totals.entry(period(day)).or_default().add(&event);
+        }
+        Ok(totals)
+    }
+
+    /// What was spent during `span` on each model
+    pub fn by_model(&self, span: impl RangeBounds<i64>) -> Result<HashMap<GptModel, Bill>> {
+        let mut totals: HashMap<GptModel, Bill> = HashMap::new();
+        for entry in self.entries(span)? {
+            totals.entry(entry.model).or_default().add(&LedgerEvent::Query(entry));
+        }
+        Ok(totals)
+    }
+
+    /// What was spent during `span` for each value of the tag `tag`, e.g. for each user with `Tags::USER`. Completions without the tag are left out
+    pub fn by_tag(&self, tag: &str, span: impl RangeBounds<i64>) -> Result<BTreeMap<String, Bill>> {
+        let mut totals: BTreeMap<String, Bill> = BTreeMap::new();
+        for entry in self.entries(span)? {
+            let value = match entry.tags.get(tag) { Some(value) => value.to_string(), None => continue };
+            totals.entry(value).or_default().add(&LedgerEvent::Query(entry));
+        }
+        Ok(totals)
+    }
+
+    /// Carry the totals of the bill file at `path`, written before the ledger was kept, into the ledger as an `Import`. Does nothing if there is no such file, it is empty, or it was imported before. Returns whether it was imported
+    /// # Errors
+    /// `Error::Io` if either file cannot be read or the ledger written, and `Error::Corrupt` if the bill cannot be parsed
+    pub fn import_bill(&self, path: impl AsRef<Path>) -> Result<bool> {
+        let path = path.as_ref();
+        let _lock = FileLock::exclusive(&self.path)?;
+        if self.read()?.iter().any(|event| matches!(event, LedgerEvent::Import { from, .. } if from == path)) { return Ok(false) }
+        let bill = match read_bill(path)? { Some(bill) if bill != Bill::default() => bill, _ => return Ok(false) };
+        self.append(&LedgerEvent::Import { at: Utc::now().timestamp_millis(), from: path.to_path_buf(), bill })?;
+        println!("🧾 Bill in {} carried into the ledger at {}", path.display(), self.path.display());
+        Ok(true)
+    }
+}
+
+/// The bill in the file at `path`, `None` if there is no such file. An empty file is a blank bill, but a corrupt one is not silently reset
+pub(crate) fn read_bill(path: &Path) -> Result<Option<Bill>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io { path: path.to_path_buf(), source: e }),
+    };
+    if text.trim().is_empty() { return Ok(Some(Bill::default())) }
+    serde_json::from_str(&text).map(Some).map_err(|e| Error::Corrupt { path: path.to_path_buf(), message: e.to_string() })
+}
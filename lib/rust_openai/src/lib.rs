@@ -15,11 +15,36 @@
 
 pub mod models;
 pub mod client;
+pub mod builder;
+pub mod cache;
 pub mod batteries;
+pub mod stream;
+pub mod rate_limit;
+pub mod conversation;
+pub mod tools;
+pub mod schema;
+pub mod history;
+pub mod fixtures;
+pub mod ledger;
+pub mod tokenizer;
+mod persist;
+mod flight;
+mod spending;
 
 pub mod constants;
+pub mod error;
 
 pub use client::OpenAIAccount;
+pub use builder::OpenAIAccountBuilder;
+pub use cache::QueryCacheBackend;
 pub use batteries::Battery;
+pub use stream::CompletionStream;
+pub use conversation::Conversation;
+pub use tools::ToolBox;
 pub use models::GptModel;
-pub use models::Query;
\ No newline at end of file
+pub use models::Query;
+pub use models::ApiConfig;
+pub use models::HttpConfig;
+pub use models::CachePolicy;
+pub use models::Mode;
+pub use error::{Error, Result};
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::env;
+
+use crate::constants::api_urls::*;
+
+
+/// How the api key is presented to the server
+#[derive(Clone, Debug)]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <api_key>`, as OpenAI and most OpenAI-compatible servers (vLLM, llama.cpp, Ollama) expect
+    Bearer,
+    /// The raw api key in the named header, e.g. Azure OpenAI's `api-key: <api_key>`
+    Header(String),
+    /// No credentials are sent, for local servers and mocks
+    None,
+}
+
+/// Where and how an `OpenAIAccount` sends its requests. <br>
+/// Request paths such as `/chat/completions` are appended to `base_url`, `headers` are sent on every request, and `query_params` are appended to every url (Azure's `api-version`).
+/// ```
+/// # use rust_openai::ApiConfig;
+/// let azure = ApiConfig::azure("my-resource", "gpt-35-16k", "2023-07-01-preview");
+/// let local = ApiConfig::local("http://localhost:8000/v1");
+/// let openai = ApiConfig::openai().with_organization("org-123");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ApiConfig {
+    pub base_url: String,
+    pub auth: AuthScheme,
+    pub headers: Vec<(String, String)>,
+    pub query_params: Vec<(String, String)>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> ApiConfig {
+        ApiConfig::openai()
+    }
+}
+
+impl ApiConfig {
+
+    /// The official OpenAI api, authenticated with a bearer token
+    pub fn openai() -> ApiConfig {
+        ApiConfig {
+            base_url: OPENAI_V1.to_string(),
+            auth: AuthScheme::Bearer,
+            headers: vec![],
+            query_params: vec![],
+        }
+    }
+
+    /// An Azure OpenAI deployment. On Azure the model is chosen by the deployment, so the `model` field of requests is ignored by the server.
+    pub fn azure(resource: &str, deployment: &str, api_version: &str) -> ApiConfig {
+        ApiConfig {
+            base_url: format!("https://{resource}.openai.azure.com/openai/deployments/{deployment}"),
+            auth: AuthScheme::Header(AZURE_API_KEY_HEADER.to_string()),
+            headers: vec![],
+            query_params: vec![(AZURE_API_VERSION_PARAM.to_string(), api_version.to_string())],
+        }
+    }
+
+    /// An OpenAI-compatible server, such as vLLM, llama.cpp's server, Ollama or a test mock, reached without credentials. <br>
+    /// `base_url` should include the version segment, e.g. `http://localhost:11434/v1`
+    pub fn local(base_url: &str) -> ApiConfig {
+        ApiConfig {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth: AuthScheme::None,
+            headers: vec![],
+            query_params: vec![],
+        }
+    }
+
+    /// Defaults to the official api, with `OPENAI_BASE_URL` overriding the url (still using bearer auth), and `OPENAI_ORG_ID` adding the organization header.
+    /// <br> This lets the whole stack be pointed at a local stand-in without code changes.
+    pub fn from_env() -> ApiConfig {
+        let mut api = ApiConfig::openai();
+        if let Ok(base_url) = env::var("OPENAI_BASE_URL") { api.base_url = base_url.trim_end_matches('/').to_string() }
+        if let Ok(organization) = env::var("OPENAI_ORG_ID") { api = api.with_organization(&organization) }
+        api
+    }
+
+    /// Sends the `OpenAI-Organization` header, so usage is billed to that organization
+    pub fn with_organization(self, organization: &str) -> ApiConfig {
+        self.with_header(OPENAI_ORGANIZATION_HEADER, organization)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> ApiConfig {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_query_param(mut self, name: &str, value: &str) -> ApiConfig {
+        self.query_params.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_auth(mut self, auth: AuthScheme) -> ApiConfig {
+        self.auth = auth;
+        self
+    }
+
+    /// The full url for an endpoint path such as `/chat/completions`, without the query parameters (those are added by the request builder)
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Apply the auth scheme, extra headers and query parameters to a request
+    pub fn authorize(&self, request: reqwest::RequestBuilder, api_key: &str) -> reqwest::RequestBuilder {
+        let request = match &self.auth {
+            AuthScheme::Bearer => request.header(reqwest::header::AUTHORIZATION, format!("Bearer {api_key}")),
+            AuthScheme::Header(name) => request.header(name.as_str(), api_key),
+            AuthScheme::None => request,
+        };
+        let request = self.headers.iter().fold(request, |request, (name, value)| request.header(name.as_str(), value.as_str()));
+        if self.query_params.is_empty() { request } else { request.query(&self.query_params) }
+    }
+}
@@ -1,15 +1,29 @@
-use std::error::Error;
-use std::fmt;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug)]
-pub struct APIError {
+
+/// The JSON body OpenAI sends alongside a non-2xx status
+/// ```json
+/// { "error": { "message": "...", "type": "invalid_request_error", "param": null, "code": "context_length_exceeded" } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiErrorResponse {
+    pub error: OpenAIErrorBody,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAIErrorBody {
     pub message: String,
+    #[serde(rename = "type", default)]
+    pub error_type: Option<String>,
+    #[serde(default)]
+    pub param: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
-impl fmt::Display for APIError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "APIError: {}", self.message)
+impl OpenAIErrorBody {
+    /// Parse the error body of a failed response, if it is in OpenAI's format
+    pub fn parse(text: &str) -> Option<OpenAIErrorBody> {
+        serde_json::from_str::<ApiErrorResponse>(text).ok().map(|res| res.error)
     }
 }
-
-impl Error for APIError {}
@@ -1,7 +1,10 @@
 use serde::{Serialize, Deserialize};
 
+use crate::ledger::LedgerEvent;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+
+/// Running totals of an account's usage, computed from its `Ledger` by `Bill::from_events()`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Bill {
     /// Total cost so far since last `.reset_bill()` in CENTS
     pub cost: f32,
@@ -15,12 +18,20 @@ pub struct Bill {
     pub query_count: i32,
     /// Number of times a ChatGPT completion was pulled from the cache instead of the API, because the prompt was found in the cache
     pub cache_retrievals: i32,
+    /// Number of entries dropped from the cache by its `CachePolicy`, whether expired or over the size limits. Evicted entries are kept in the query history
+    #[serde(default)]
+    pub cache_evictions: i32,
+    /// What the completions pulled from the cache would have cost, in CENTS
+    #[serde(default)]
+    pub cache_savings: f32,
 }
 
 impl Default for Bill {
     fn default() -> Bill {
         Bill {
             cache_retrievals: 0,
+            cache_evictions: 0,
+            cache_savings: 0.00,
             completion_tokens: 0,
             prompt_tokens: 0,
             cost: 0.00,
@@ -28,4 +39,41 @@ impl Default for Bill {
             total_tokens: 0
         }
     }
-}
\ No newline at end of file
+}
+impl Bill {
+
+    /// The bill of the events of a ledger, oldest first: everything after the last `Reset`
+    pub fn from_events(events: &[LedgerEvent]) -> Bill {
+        let since = events.iter().rposition(|event| matches!(event, LedgerEvent::Reset { .. })).map_or(0, |reset| reset + 1);
+        events[since..].iter().fold(Bill::default(), |mut bill, event| { bill.add(event); bill })
+    }
+
+    /// Count `event` in this bill. A `Reset` zeroes every field
+    pub fn add(&mut self, event: &LedgerEvent) {
+        match event {
+            LedgerEvent::Query(entry) if entry.cache_hit => {
+                self.cache_retrievals += 1;
+                self.cache_savings += entry.cost;
+            },
+            LedgerEvent::Query(entry) => {
+                self.cost += entry.cost;
+                self.prompt_tokens += entry.prompt_tokens;
+                self.completion_tokens += entry.completion_tokens;
+                self.total_tokens += entry.total_tokens;
+                self.query_count += 1;
+            },
+            LedgerEvent::Eviction { count, .. } => self.cache_evictions += count,
+            LedgerEvent::Reset { .. } => *self = Bill::default(),
+            LedgerEvent::Import { bill, .. } => {
+                self.cost += bill.cost;
+                self.prompt_tokens += bill.prompt_tokens;
+                self.completion_tokens += bill.completion_tokens;
+                self.total_tokens += bill.total_tokens;
+                self.query_count += bill.query_count;
+                self.cache_retrievals += bill.cache_retrievals;
+                self.cache_evictions += bill.cache_evictions;
+                self.cache_savings += bill.cache_savings;
+            },
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+
+use chrono::{Datelike, TimeZone, Utc};
+use serde::{Serialize, Deserialize};
+
+use super::tags::Tags;
+use crate::ledger::LedgerEntry;
+
+
+/// How much the users, libraries and jobs named in a request's `Tags` may spend, checked before every request is sent. <br>
+/// Each of them is held to the limit of its membership tier (the `Tags::MEMBERSHIP` of the request, `Unpaid` if it has none), unless `overrides` gives it one of its own.
+/// A request that could take any of them past a limit, counting what it costs at most (see `Estimate`), fails with `Error::BudgetExceeded` and is not sent; past `soft_limit` of a limit, a warning is printed. A request without a user fails with `Error::Untagged`, unless `.allow_untagged()`.
+/// <br> What a request costs at most depends on its `max_tokens`: without one, the completion is counted as filling the context window, so that an Unpaid job's 50¢ covers a single gpt-4 request, and gpt-4-32k (about $3.90 at most) is out of an Unpaid user's reach. Cap completions with `OpenAIAccountBuilder::max_tokens()` to spend budgets on what requests may actually use.
+/// Spending is read from the account's `Ledger`, so budgets hold across processes sharing it. While a request is in flight, what it may cost at most is reserved by the account and its clones, so concurrent requests cannot together go past a limit that each fits within alone.
+/// ```no_run
+/// # use rust_openai::models::{Budget, Budgets, Limit, Membership, Period, Tags};
+/// # use rust_openai::OpenAIAccountBuilder;
+/// let budgets = Budgets::default().with_override(Budget { tag: Tags::LIBRARY.to_string(), value: "lib-7".to_string(), limit: Limit::cents(20_000.0, Period::Month) });
+/// let account = OpenAIAccountBuilder::from_env().unwrap().ledger_file("ledger.jsonl").budgets(budgets).build().unwrap();
+/// let job = account.with_tags(Tags::new().user("42").membership(Membership::Unpaid).library("lib-7").job("job-3"));
+/// ```
+/// What the default tiers allow, for gpt-4 requests with a 1000-token prompt:
+/// ```
+/// # use rust_openai::models::{Budgets, ChatCompletionRequest};
+/// # use rust_openai::{GptModel, OpenAIAccount};
+/// let account = OpenAIAccount::builder("sk-...").build().unwrap();
+/// let (unpaid, paid) = (Budgets::default().unpaid, Budgets::default().paid);
+/// let request = ChatCompletionRequest::from_prompt(GptModel::Gpt4, "hello ".repeat(1000), 0.0);
+/// let allowed = |cents: f32, max_tokens: Option<u32>| (cents / account.estimate(&request.clone().with_max_tokens(max_tokens)).unwrap().max_cost) as usize;
+/// // Counted as filling the context window, an Unpaid job gets a single request, and an Unpaid user two a month
+/// assert_eq!((allowed(unpaid.per_job.unwrap().cents, None), allowed(unpaid.per_user.unwrap().cents, None)), (1, 2));
+/// assert_eq!((allowed(paid.per_job.unwrap().cents, None), allowed(paid.per_user.unwrap().cents, None)), (21, 108));
+/// // With completions capped at 500 tokens
+/// assert_eq!((allowed(unpaid.per_job.unwrap().cents, Some(500)), allowed(unpaid.per_user.unwrap().cents, Some(500))), (8, 16));
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Budgets {
+    pub unpaid: TierLimits,
+    pub paid: TierLimits,
+    /// Limits for one user, library or job, replacing that of its tier
+    #[serde(default)]
+    pub overrides: Vec<Budget>,
+    /// Share of a limit past which each request prints a warning, e.g. `0.8` to warn at 80%
+    pub soft_limit: f32,
+    /// Refuse requests without a `Tags::USER` with `Error::Untagged`, so none escapes the budgets by not being attributed. Default value is `true`
+    #[serde(default = "require_user")]
+    pub require_user: bool,
+}
+
+/// The limits of the users, libraries and jobs of one membership tier. `None` leaves them unlimited
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TierLimits {
+    pub per_user: Option<Limit>,
+    pub per_library: Option<Limit>,
+    pub per_job: Option<Limit>,
+}
+
+/// The limit of one tag value, e.g. of the library `lib-7`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Budget {
+    /// `Tags::USER`, `Tags::LIBRARY` or `Tags::JOB`
+    pub tag: String,
+    pub value: String,
+    pub limit: Limit,
+}
+
+/// At most `cents` spent per `period`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Limit {
+    pub cents: f32,
+    pub period: Period,
+}
+
+/// The span of time spending is counted over, in UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Period {
+    Day,
+    Month,
+    /// Everything ever spent, e.g. for a job
+    Total,
+}
+
+/// The membership tier of a user, as in the `Membership` enum of the Prisma schema
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Membership {
+    #[default]
+    Unpaid,
+    Paid,
+}
+
+/// What one tag value has spent against its limit
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BudgetUsage {
+    pub tag: String,
+    pub value: String,
+    pub limit: Limit,
+    /// Spent in the current period, in CENTS
+    pub spent: f32,
+    /// Held for requests sent but not yet recorded, at what each may cost at most, in CENTS
+    pub reserved: f32,
+}
+
+impl Default for Budgets {
+    /// Unpaid: $1 a month per user and per library, 50¢ per job. Paid: $50 a month per user, $20 a month per library, $10 per job. Warns at 80%
+    fn default() -> Budgets {
+        Budgets {
+            unpaid: TierLimits {
+                per_user: Some(Limit::cents(100.0, Period::Month)),
+                per_library: Some(Limit::cents(100.0, Period::Month)),
+                per_job: Some(Limit::cents(50.0, Period::Total)),
+            },
+            paid: TierLimits {
+                per_user: Some(Limit::cents(5_000.0, Period::Month)),
+                per_library: Some(Limit::cents(2_000.0, Period::Month)),
+                per_job: Some(Limit::cents(1_000.0, Period::Total)),
+            },
+            overrides: Vec::new(),
+            soft_limit: 0.8,
+            require_user: true,
+        }
+    }
+}
+
+impl Budgets {
+
+    /// Add `budget`, replacing any override of the same tag value
+    pub fn with_override(mut self, budget: Budget) -> Budgets {
+        self.overrides.retain(|old| old.tag != budget.tag || old.value != budget.value);
+        self.overrides.push(budget);
+        self
+    }
+
+    pub fn with_soft_limit(mut self, share: f32) -> Budgets {
+        self.soft_limit = share;
+        self
+    }
+
+    /// Let requests without a `Tags::USER` through, held only to the limits of the library or job they name, if any
+    pub fn allow_untagged(mut self) -> Budgets {
+        self.require_user = false;
+        self
+    }
+
+    pub fn tier(&self, membership: Membership) -> &TierLimits {
+        match membership {
+            Membership::Unpaid => &self.unpaid,
+            Membership::Paid => &self.paid,
+        }
+    }
+
+    /// The limit of `value` of the tag `tag`, in `membership`'s tier unless it has an override
+    pub fn limit(&self, tag: &str, value: &str, membership: Membership) -> Option<Limit> {
+        if let Some(budget) = self.overrides.iter().find(|budget| budget.tag == tag && budget.value == value) { return Some(budget.limit) }
+        let tier = self.tier(membership);
+        match tag {
+            Tags::USER => tier.per_user,
+            Tags::LIBRARY => tier.per_library,
+            Tags::JOB => tier.per_job,
+            _ => None,
+        }
+    }
+
+    /// The limit of each user, library and job in `tags` that has one, in the tier of the `Tags::MEMBERSHIP` of `tags`
+    pub fn limits_of(&self, tags: &Tags) -> Vec<Budget> {
+        let membership = tags.get(Tags::MEMBERSHIP).map_or(Membership::Unpaid, Membership::from_tag);
+        [Tags::USER, Tags::LIBRARY, Tags::JOB].into_iter().filter_map(|tag| {
+            let value = tags.get(tag)?;
+            let limit = self.limit(tag, value, membership)?;
+            Some(Budget { tag: tag.to_string(), value: value.to_string(), limit })
+        }).collect()
+    }
+
+    /// What each user, library and job in `tags` that has a limit has spent in its current period at `now`, in unix milliseconds, according to `entries` of the ledger
+    pub fn usage(&self, tags: &Tags, entries: &[LedgerEntry], now: i64) -> Vec<BudgetUsage> {
+        self.limits_of(tags).into_iter().map(|Budget { tag, value, limit }| {
+            let since = limit.period.start(now);
+            let spent = entries.iter()
+                .filter(|entry| !entry.cache_hit && entry.at >= since && entry.tags.get(&tag) == Some(value.as_str()))
+                .fold(0.0, |spent, entry| spent + entry.cost);
+            BudgetUsage { tag, value, limit, spent, reserved: 0.0 }
+        }).collect()
+    }
+}
+
+fn require_user() -> bool {
+    true
+}
+
+impl Limit {
+    pub fn cents(cents: f32, period: Period) -> Limit {
+        Limit { cents, period }
+    }
+}
+
+impl Period {
+
+    /// Unix milliseconds at which the period holding `now` began
+    pub fn start(&self, now: i64) -> i64 {
+        let today = Utc.timestamp_millis_opt(now).single().map_or(chrono::NaiveDate::MIN, |now| now.date_naive());
+        let start = match self {
+            Period::Day => today,
+            Period::Month => today.with_day(1).unwrap_or(today),
+            Period::Total => return i64::MIN,
+        };
+        start.and_hms_opt(0, 0, 0).map_or(i64::MIN, |midnight| midnight.and_utc().timestamp_millis())
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Period::Day => "today",
+            Period::Month => "this month",
+            Period::Total => "in total",
+        })
+    }
+}
+
+impl Membership {
+
+    /// The tier named by a `Tags::MEMBERSHIP` tag. Anything but `Paid` is `Unpaid`
+    pub fn from_tag(value: &str) -> Membership {
+        if value == "Paid" { Membership::Paid } else { Membership::Unpaid }
+    }
+}
+
+impl BudgetUsage {
+
+    /// What a request costing at most `cents` would leave of the limit once every reserved request is paid for, in CENTS. Negative if it could go past it
+    pub fn remaining_after(&self, cents: f32) -> f32 {
+        self.limit.cents - self.spent - self.reserved - cents
+    }
+}
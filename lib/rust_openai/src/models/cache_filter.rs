@@ -0,0 +1,78 @@
+use super::gpt_models::GptModel;
+use super::query::{Query, QueryType};
+use super::Tags;
+
+
+/// Which cache entries `OpenAIAccount::list_cache` and `.export_cache()` pick. The default picks every entry. <br>
+/// Dates are unix milliseconds compared with each entry's `cached_at`, so entries cached before timestamps were kept fall outside any date bound. Costs are in CENTS.
+/// ```
+/// # use rust_openai::models::{CacheFilter, GptModel, QueryType};
+/// let costly_pdfs = CacheFilter::default()
+///     .with_query_type(QueryType::PdfCompletion)
+///     .with_model(GptModel::Gpt4)
+///     .with_min_cost(5.0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CacheFilter {
+    pub query_type: Option<QueryType>,
+    pub model: Option<GptModel>,
+    pub cached_after: Option<i64>,
+    pub cached_before: Option<i64>,
+    pub min_cost: Option<f32>,
+    pub max_cost: Option<f32>,
+    /// Entries whose Query carries every one of these tags
+    pub tags: Tags,
+}
+
+impl CacheFilter {
+
+    pub fn with_query_type(mut self, query_type: QueryType) -> CacheFilter {
+        self.query_type = Some(query_type);
+        self
+    }
+
+    pub fn with_model(mut self, model: GptModel) -> CacheFilter {
+        self.model = Some(model);
+        self
+    }
+
+    /// Entries cached at or after `at`, in unix milliseconds
+    pub fn with_cached_after(mut self, at: i64) -> CacheFilter {
+        self.cached_after = Some(at);
+        self
+    }
+
+    /// Entries cached before `at`, in unix milliseconds
+    pub fn with_cached_before(mut self, at: i64) -> CacheFilter {
+        self.cached_before = Some(at);
+        self
+    }
+
+    pub fn with_min_cost(mut self, cents: f32) -> CacheFilter {
+        self.min_cost = Some(cents);
+        self
+    }
+
+    pub fn with_max_cost(mut self, cents: f32) -> CacheFilter {
+        self.max_cost = Some(cents);
+        self
+    }
+
+    /// Entries whose Query has the tag `key` set to `value`
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> CacheFilter {
+        self.tags = self.tags.with(key, value);
+        self
+    }
+
+    /// Whether `query` passes every bound of this filter
+    pub fn matches(&self, query: &Query) -> bool {
+        let cached_at = query.cached_at;
+        self.query_type.is_none_or(|query_type| query.query_type == query_type)
+            && self.model.is_none_or(|model| query.model == model)
+            && self.cached_after.is_none_or(|after| cached_at.is_some_and(|at| at >= after))
+            && self.cached_before.is_none_or(|before| cached_at.is_some_and(|at| at < before))
+            && self.min_cost.is_none_or(|min| query.cost >= min)
+            && self.max_cost.is_none_or(|max| query.cost <= max)
+            && self.tags.iter().all(|(key, value)| query.tags.get(key) == Some(value))
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::query::{Query, QueryType};
+
+
+/// When an `OpenAIAccount` drops entries from its cache. The default keeps everything forever, as the cache always did. <br>
+/// - `ttl`: entries of a `QueryType` older than its TTL are treated as a miss and evicted. Types without a TTL, and entries cached before timestamps were kept, never expire.
+/// - `max_entries` / `max_bytes`: after every new entry, entries are evicted in `eviction` order until the cache fits. Bytes are counted as the size of each key and its Query in JSON.
+///
+/// Evicted entries are archived in the query history, and counted in the bill's `cache_evictions`. Entries cached before timestamps were kept count as the oldest.
+/// ```
+/// # use std::time::Duration;
+/// # use rust_openai::models::{CachePolicy, Eviction, QueryType};
+/// let policy = CachePolicy::default()
+///     .with_ttl(QueryType::PromptCompletion, Duration::from_secs(7 * 24 * 60 * 60))
+///     .with_max_entries(10_000)
+///     .with_eviction(Eviction::OldestFirst);
+/// assert!(!policy.is_unbounded());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CachePolicy {
+    pub ttl: HashMap<QueryType, Duration>,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub eviction: Eviction,
+}
+
+/// Which entries go first when the cache is over its `CachePolicy` limits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// Least recently hit first, or least recently cached for backends that do not record hits
+    #[default]
+    LeastRecentlyUsed,
+    /// Least recently cached first, however often it was hit since
+    OldestFirst,
+}
+
+impl CachePolicy {
+
+    pub fn with_ttl(mut self, query_type: QueryType, ttl: Duration) -> CachePolicy {
+        self.ttl.insert(query_type, ttl);
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> CachePolicy {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> CachePolicy {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_eviction(mut self, eviction: Eviction) -> CachePolicy {
+        self.eviction = eviction;
+        self
+    }
+
+    /// Whether nothing is ever evicted under this policy
+    pub fn is_unbounded(&self) -> bool {
+        self.ttl.is_empty() && self.max_entries.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Whether `query` has outlived the TTL of its type at `now`, in unix milliseconds. Queries cached before timestamps were kept never expire, as their age is unknown
+    pub fn is_expired(&self, query: &Query, now: i64) -> bool {
+        match (self.ttl.get(&query.query_type), query.cached_at) {
+            (Some(ttl), Some(cached_at)) => now - cached_at > ttl.as_millis() as i64,
+            _ => false,
+        }
+    }
+
+    /// Whether `entries` entries adding up to `bytes` are more than this policy allows
+    pub fn is_over(&self, entries: usize, bytes: usize) -> bool {
+        self.max_entries.is_some_and(|max| entries > max) || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+
+    /// Sort key of `query` in eviction order: the lowest is evicted first
+    pub fn rank(&self, query: &Query) -> i64 {
+        match self.eviction {
+            Eviction::LeastRecentlyUsed => query.last_used.or(query.cached_at).unwrap_or(0),
+            Eviction::OldestFirst => query.cached_at.unwrap_or(0),
+        }
+    }
+}
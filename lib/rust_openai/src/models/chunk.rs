@@ -0,0 +1,39 @@
+use {
+    serde::{Serialize,Deserialize},
+    super::req_and_res::{MessageRole, FunctionCall, Usage},
+    super::response::FinishReason,
+};
+
+
+/// One server-sent event of a `stream: Some(true)` chat completion. <br>
+/// Each chunk holds only the new piece (`delta`) of the message; the pieces are stitched back together by `CompletionStream` into a regular `ChatCompletionResponse`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChunkChoice>,
+    /// Only present on the last chunk, and only when the request was sent with `StreamOptions { include_usage: true }`
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ChatCompletionChunkChoice {
+    pub index: i64,
+    pub delta: ChatCompletionDelta,
+    #[serde(default)]
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// The part of a `ChatCompletionMessage` that arrived in a single chunk. The role is only sent on the first chunk, the content on every chunk after it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ChatCompletionDelta {
+    #[serde(default)]
+    pub role: Option<MessageRole>,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub function_call: Option<FunctionCall>,
+}
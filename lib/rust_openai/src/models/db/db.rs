@@ -1,21 +1,73 @@
-use super::query_cache::Model;
+use sea_orm::ActiveValue;
+
+use super::query_cache::{ActiveModel, Model};
 use crate::models::*;
+use crate::models::hash::calculate_hash;
+use crate::cache::key;
+use crate::error::{Error, Result};
 
+/// Format of the `timestamp` column
+const TIMESTAMP_FORMAT: &str = "%d/%m/%Y %H:%M:%S";
 
 impl Model {
-    pub fn to_query(&self) -> Query {
+    pub fn to_query(&self) -> Result<Query> {
         
-        Query { 
+        Ok(Query { 
             prompt: self.prompt.clone(), 
             cost: self.cost, 
-            response: serde_json::from_value(self.response.clone()).unwrap(), 
+            response: serde_json::from_value(self.response.clone()).map_err(Error::json(format!("the response of query_cache row {}", self.rid)))?, 
             process_time: self.process_time as u64, 
-            model: GptModel::from_string(&self.model), 
-            query_type: if self.query_key == self.prompt {QueryType::PromptCompletion } else if self.query_key.contains("Meta-Battery") {QueryType::MetaCompletion} else {QueryType::PdfCompletion}, 
+            model: GptModel::from_string(&self.model)?, 
+            query_type: self.query_type(), 
             temperature: self.temperature,
             from_cache: true, 
-        }
+            cached_at: self.cached_at(),
+            last_used: None,
+            tags: match &self.tags {
+                Some(tags) => serde_json::from_value(tags.clone()).map_err(Error::json(format!("the tags of query_cache row {}", self.rid)))?,
+                None => Tags::default(),
+            },
+        })
+
+    }
+
+}
 
+impl Model {
+    /// The `timestamp` column as unix milliseconds, read in the local timezone it was written in
+    fn cached_at(&self) -> Option<i64> {
+        let naive = chrono::NaiveDateTime::parse_from_str(&self.timestamp, TIMESTAMP_FORMAT).ok()?;
+        naive.and_local_timezone(chrono::Local).earliest().map(|time| time.timestamp_millis())
+    }
+
+    /// The type of the Query in this row, told apart by the label of its key (see `cache::key::label()`): a prompt completion is labelled with its own prompt, a meta completion with a "Meta-Battery" stamp, and a conversation turn with `"{title} - Conversation"`
+    fn query_type(&self) -> QueryType {
+        let label = key::label(&self.query_key);
+        if label == self.prompt || label == self.prompt.to_lowercase().replace('\n', " ") {QueryType::PromptCompletion}
+        else if label.contains("Meta-Battery") {QueryType::MetaCompletion}
+        else if label.contains(" - Conversation") {QueryType::Conversation}
+        else {QueryType::PdfCompletion}
     }
+}
 
-}
\ No newline at end of file
+impl ActiveModel {
+    /// The row to insert for the Query cached at `cache_key`, timestamped now
+    pub fn from_query(cache_key: &str, query: &Query) -> Result<ActiveModel> {
+        Ok(ActiveModel { 
+            timestamp: ActiveValue::Set(chrono::Local::now().format(TIMESTAMP_FORMAT).to_string()), 
+            model: ActiveValue::Set(query.model.to_string()), 
+            temperature: ActiveValue::Set(query.temperature), 
+            prompt: ActiveValue::Set(query.prompt.to_string()),
+            query_key: ActiveValue::Set(cache_key.to_string()), 
+            prompt_tokens: ActiveValue::Set(query.response.usage.prompt_tokens), 
+            completion_tokens: ActiveValue::Set(query.response.usage.completion_tokens), 
+            total_tokens: ActiveValue::Set(query.response.usage.total_tokens), 
+            process_time: ActiveValue::Set(query.process_time as i32), 
+            response: ActiveValue::Set(serde_json::to_value(query.response.clone()).map_err(Error::json("query.response to a JSON value"))?), 
+            cost: ActiveValue::Set(query.cost),
+            tags: ActiveValue::Set(if query.tags.is_empty() { None } else { Some(serde_json::to_value(&query.tags).map_err(Error::json("query.tags to a JSON value"))?) }),
+            query_key_hash: ActiveValue::Set(calculate_hash(cache_key)), 
+            rid: ActiveValue::NotSet
+        })
+    }
+}
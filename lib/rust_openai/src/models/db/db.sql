@@ -12,10 +12,14 @@ CREATE TABLE query_cache (
     process_time int NOT NULL,
     response json NOT NULL,
     cost float NOT NULL,
+    tags json DEFAULT NULL,
     PRIMARY KEY (rid),
     UNIQUE KEY query_key_hash_UNIQUE (query_key_hash)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci
 
+-- Tables created before queries carried attribution tags:
+-- ALTER TABLE query_cache ADD COLUMN tags json DEFAULT NULL;
+
 
 /* 
 
@@ -24,6 +24,7 @@ pub struct Model {
     pub response: Json,
     #[sea_orm(column_type = "Float")]
     pub cost: f32,
+    pub tags: Option<Json>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
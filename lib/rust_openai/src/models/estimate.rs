@@ -0,0 +1,39 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use super::gpt_models::GptModel;
+
+
+/// What a request will use and may cost, worked out locally before it is sent, see `OpenAIAccount::estimate()`. <br>
+/// The completion can take up to the request's `max_tokens`, or else whatever the prompt leaves of the context window, so `max_cost` is what the request costs at most.
+/// Without `max_tokens` that bound is pessimistic: a gpt-4 request is priced as if its completion filled 8k tokens, however short the answer turns out.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Estimate {
+    pub model: GptModel,
+    pub prompt_tokens: usize,
+    /// The most the completion can use: the request's `max_tokens`, or else what the prompt leaves of the context window
+    pub max_completion_tokens: usize,
+    pub context_window: usize,
+    /// The prompt plus the longest possible completion at today's price, in CENTS. Nothing if the completion would be served from the cache
+    pub max_cost: f32,
+    /// The completion is already in the cache, so nothing would be sent
+    pub cached: bool,
+}
+
+impl Estimate {
+
+    /// Whether the prompt and the longest completion fit the context window, with room for at least one completion token
+    pub fn fits(&self) -> bool {
+        self.prompt_tokens < self.context_window && self.prompt_tokens + self.max_completion_tokens <= self.context_window
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let model = self.model.to_string();
+        if self.cached { return write!(f, "Cached: {} prompt tokens on {model}, nothing to send", self.prompt_tokens) }
+        if !self.fits() { return write!(f, "Too long: {} prompt tokens and up to {} completion tokens do not fit the {}-token context window of {model}", self.prompt_tokens, self.max_completion_tokens, self.context_window) }
+        write!(f, "{} prompt tokens on {model}, leaving {} for the completion, at most ${:.4}", self.prompt_tokens, self.max_completion_tokens, self.max_cost / 100.0)
+    }
+}
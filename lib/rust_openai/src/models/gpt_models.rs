@@ -1,6 +1,7 @@
 
 use {
     serde::{Serialize,Deserialize},
+    crate::error::{Error, Result},
 };
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
@@ -43,14 +44,29 @@ impl ToString for GptModel {
 }
 
 impl GptModel {
+    /// How many tokens the model reads and writes in one request, prompt and completion together
+    pub fn context_window(&self) -> usize {
+        use GptModel::*;
+        match self {
+            Gpt35Turbo | Gpt35Turbo0613 => 4_096,
+            Gpt35Turbo16k => 16_384,
+            Gpt4 | Gpt40314 | Gpt40613 => 8_192,
+            Gpt432k | Gpt432k0314 => 32_768,
+        }
+    }
+
     /// Convert an OpenAI model name string into the corresponding variant of GptModel
     /// ```
-    /// let model:GptModel::Gpt35Turbo = GptModel::from_string(GPT3_5_TURBO);
+    /// # use rust_openai::{GptModel, constants::model_strings::GPT3_5_TURBO};
+    /// let model = GptModel::from_string(&GPT3_5_TURBO.to_string()).unwrap();
+    /// assert_eq!(model, GptModel::Gpt35Turbo);
     /// ```
-    pub fn from_string(model: &String) -> GptModel {
+    /// # Errors
+    /// `Error::UnknownModel` if the string matches none of the variants
+    pub fn from_string(model: &String) -> Result<GptModel> {
         use GptModel::*;
         use crate::constants::model_strings::*;
-        match model.as_str() {
+        Ok(match model.as_str() {
             GPT3_5_TURBO => Gpt35Turbo,
             GPT3_5_TURBO_16K => Gpt35Turbo16k,
             GPT3_5_TURBO_0613 => Gpt35Turbo0613,
@@ -59,8 +75,9 @@ impl GptModel {
             GPT4_32K => Gpt432k,
             GPT4_32K_0314 => Gpt432k0314,
             GPT4_0613 => Gpt40613,
-            &_ => panic!("A new model needs added or else `GptModel::from_string(string)` was called with an invalid model string: {model}")
-        }
+            // A new model needs added, or else this was called with an invalid model string
+            &_ => return Err(Error::UnknownModel(model.to_string()))
+        })
     }
 }
 
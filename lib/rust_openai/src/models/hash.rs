@@ -1,10 +1,8 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use sha2::{Digest, Sha256};
 
 
 
-pub fn calculate_hash<T: Hash>(t: &T) -> String {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish().to_string()
-}
\ No newline at end of file
+/// The SHA-256 of `data`, as 64 lowercase hex characters. Stable across Rust releases and platforms, so it can be stored, e.g. as the `query_key_hash` of the `query_cache` table.
+pub fn calculate_hash(data: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(data.as_ref()))
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+
+/// Settings of the `reqwest::Client` an `OpenAIAccount` keeps for its whole lifetime, so that connections and TLS sessions are reused between requests. <br>
+/// - `connect_timeout`: how long establishing a connection (TCP + TLS) may take
+/// - `read_timeout`: how long to wait for OpenAI to send anything. For a blocking completion this is the wait for the whole response, as OpenAI only answers once it is generated, so it must leave room for the slowest (32k) completions. For a `CompletionStream` it is the longest allowed gap between two chunks.
+/// - `timeout`: the limit on a request as a whole, including reading a streamed body to its end
+///
+/// A request that times out is retried like a dropped connection, see `RetryPolicy`. `None` disables that timeout.
+/// <br> Proxies in the `HTTP_PROXY`/`HTTPS_PROXY` environment variables are used unless `no_system_proxy` is set; `proxy` adds an explicit proxy for every request.
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    /// Proxy url for every request, e.g. `"http://proxy.internal:3128"`
+    pub proxy: Option<String>,
+    pub no_system_proxy: bool,
+    pub user_agent: String,
+    /// PEM files of extra root certificates to trust, e.g. for a local server or a TLS-intercepting proxy
+    pub root_certificates: Vec<PathBuf>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> HttpConfig {
+        HttpConfig {
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(300)),
+            timeout: Some(Duration::from_secs(600)),
+            proxy: None,
+            no_system_proxy: false,
+            user_agent: format!("rust_openai/{}", env!("CARGO_PKG_VERSION")),
+            root_certificates: vec![],
+        }
+    }
+}
+
+impl HttpConfig {
+
+    pub fn with_proxy(mut self, url: &str) -> HttpConfig {
+        self.proxy = Some(url.to_string());
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: &str) -> HttpConfig {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    pub fn with_root_certificate(mut self, pem_path: impl Into<PathBuf>) -> HttpConfig {
+        self.root_certificates.push(pem_path.into());
+        self
+    }
+
+    /// Build the client these settings describe. <br>
+    /// # Errors
+    /// `Error::Io` if a root certificate cannot be read, `Error::Http` if it is not valid PEM, the proxy url is invalid, or the TLS backend fails to initialize
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().user_agent(&self.user_agent);
+
+        if let Some(timeout) = self.connect_timeout { builder = builder.connect_timeout(timeout) }
+        if let Some(timeout) = self.timeout { builder = builder.timeout(timeout) }
+        if self.no_system_proxy { builder = builder.no_proxy() }
+        if let Some(proxy) = &self.proxy { builder = builder.proxy(reqwest::Proxy::all(proxy)?) }
+
+        for path in &self.root_certificates {
+            let pem = std::fs::read(path).map_err(Error::io(path))?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+
+        Ok(builder.build()?)
+    }
+}
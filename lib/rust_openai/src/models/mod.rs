@@ -7,6 +7,17 @@ pub mod bill;
 pub mod query;
 pub mod db;
 pub mod hash;
+pub mod chunk;
+pub mod api_config;
+pub mod retry;
+pub mod http_config;
+pub mod cache_policy;
+pub mod mode;
+pub mod cache_filter;
+pub mod pricing;
+pub mod tags;
+pub mod estimate;
+pub mod budget;
 
 
 // Hoist up these structs into the "::models::{}" scope, out from their individual files (they are still available there too)
@@ -14,7 +25,17 @@ pub use req_and_res::ChatCompletionMessage;
 pub use req_and_res::MessageRole;
 pub use request::ChatCompletionRequest;
 pub use response::ChatCompletionResponse;
+pub use chunk::ChatCompletionChunk;
 pub use bill::Bill;
 pub use query::Query;
 pub use query::QueryType;
-pub use gpt_models::GptModel;
\ No newline at end of file
+pub use gpt_models::GptModel;
+pub use api_config::ApiConfig;
+pub use http_config::HttpConfig;
+pub use cache_policy::{CachePolicy, Eviction};
+pub use mode::Mode;
+pub use cache_filter::CacheFilter;
+pub use pricing::{Price, PricingTable};
+pub use tags::Tags;
+pub use estimate::Estimate;
+pub use budget::{Budget, BudgetUsage, Budgets, Limit, Membership, Period, TierLimits};
\ No newline at end of file
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+
+/// Where an `OpenAIAccount` gets the completions it does not find in its cache. Default is `Live`. <br>
+/// Apart from where the answer comes from, every mode caches and bills a completion the same way, so a pipeline such as `apply_battery_to_pdf` followed by `meta_complete_cache` behaves the same under each.
+/// ```no_run
+/// # use rust_openai::{OpenAIAccountBuilder, models::Mode};
+/// // Record once, with network access
+/// let recording = OpenAIAccountBuilder::from_env().unwrap().mode(Mode::Record("fixtures.jsonl".into())).build().unwrap();
+/// // Then replay in CI, where no request is ever sent
+/// let replaying = OpenAIAccountBuilder::new("").mode(Mode::Replay("fixtures.jsonl".into())).build().unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Ask OpenAI
+    #[default]
+    Live,
+    /// Never ask OpenAI: a completion missing from the cache fails with `Error::CacheMiss`
+    CacheOnly,
+    /// Ask OpenAI, and append every request and its response to the fixture file at this path, see `Fixtures`
+    Record(PathBuf),
+    /// Never ask OpenAI: answer from the fixture file written in `Record` mode, failing with `Error::NotRecorded` on a request that is not in it
+    Replay(PathBuf),
+}
+
+impl Mode {
+
+    /// Whether completions are requested from OpenAI in this mode
+    pub fn is_online(&self) -> bool {
+        matches!(self, Mode::Live | Mode::Record(_))
+    }
+}
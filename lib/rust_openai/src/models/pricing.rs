@@ -0,0 +1,108 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::{Serialize, Deserialize};
+
+use super::gpt_models::GptModel;
+use super::req_and_res::Usage;
+use crate::error::{Error, Result};
+
+
+/// What OpenAI charges per model, and since when. Every cost in the crate is computed by `.cost_in_cents()`, with the price that applied when the query ran. <br>
+/// The built-in table holds OpenAI's published prices. A pricing file such as `pricing.json` adds prices to it, or replaces those with the same model and effective date, so a price change needs no new release:
+/// ```json
+/// { "prices": [ { "model": "Gpt4", "effective": "2024-01-01", "prompt_per_1k": 0.03, "completion_per_1k": 0.06 } ] }
+/// ```
+/// ```
+/// # use rust_openai::models::{GptModel, PricingTable};
+/// # use rust_openai::models::req_and_res::Usage;
+/// let usage = Usage { prompt_tokens: 1000, completion_tokens: 1000, total_tokens: 2000 };
+/// let cents = PricingTable::default().cost_in_cents(GptModel::Gpt4, &usage, chrono::Utc::now().timestamp_millis());
+/// assert!((cents - 9.0).abs() < 1e-4);
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PricingTable {
+    pub prices: Vec<Price>,
+}
+
+/// The rates of one model from its `effective` day (UTC) until the next price of the same model
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    pub model: GptModel,
+    pub effective: NaiveDate,
+    /// US dollars per 1000 prompt tokens, as OpenAI quotes them
+    pub prompt_per_1k: f32,
+    /// US dollars per 1000 completion tokens, as OpenAI quotes them
+    pub completion_per_1k: f32,
+}
+
+impl Default for PricingTable {
+    fn default() -> PricingTable {
+        use GptModel::*;
+        let price = |model, (year, month, day), prompt_per_1k, completion_per_1k| Price {
+            model,
+            effective: NaiveDate::from_ymd_opt(year, month, day).unwrap_or_default(),
+            prompt_per_1k,
+            completion_per_1k,
+        };
+        PricingTable { prices: vec![
+            price(Gpt35Turbo, (2023, 3, 1), 0.002, 0.002),
+            price(Gpt35Turbo, (2023, 6, 13), 0.0015, 0.002), //4k
+            price(Gpt35Turbo0613, (2023, 6, 13), 0.0015, 0.002), // 4k
+            price(Gpt35Turbo16k, (2023, 6, 13), 0.003, 0.004), //16k
+
+            price(Gpt4, (2023, 3, 14), 0.03, 0.06),
+            price(Gpt40314, (2023, 3, 14), 0.03, 0.06),
+            price(Gpt40613, (2023, 6, 13), 0.03, 0.06),
+
+            price(Gpt432k, (2023, 3, 14), 0.06, 0.12),
+            price(Gpt432k0314, (2023, 3, 14), 0.06, 0.12),
+        ]}
+    }
+}
+
+impl PricingTable {
+
+    /// The built-in table, with the prices in the pricing file at `path` added. A missing file leaves the built-in table as is
+    /// # Errors
+    /// `Error::Io` if the file cannot be read, and `Error::Json` if it cannot be parsed
+    pub fn load(path: impl AsRef<Path>) -> Result<PricingTable> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("💲 No pricing file at {}, using the built-in prices", path.display());
+                return Ok(PricingTable::default())
+            },
+            Err(e) => return Err(Error::Io { path: path.to_path_buf(), source: e }),
+        };
+        let file: PricingTable = serde_json::from_str(&text).map_err(Error::json(format!("the pricing file {}", path.display())))?;
+        println!("💲 {} prices read from: {}", file.prices.len(), path.display());
+        Ok(file.prices.into_iter().fold(PricingTable::default(), PricingTable::with_price))
+    }
+
+    /// Add `price`, replacing any price of the same model with the same effective day
+    pub fn with_price(mut self, price: Price) -> PricingTable {
+        self.prices.retain(|old| old.model != price.model || old.effective != price.effective);
+        self.prices.push(price);
+        self
+    }
+
+    /// The price of `model` at `at`, in unix milliseconds: the latest to take effect by then, or the earliest known for a query older than any price
+    pub fn price(&self, model: GptModel, at: i64) -> Option<&Price> {
+        let day = Utc.timestamp_millis_opt(at).single().map_or(NaiveDate::MIN, |at| at.date_naive());
+        let mut prices: Vec<&Price> = self.prices.iter().filter(|price| price.model == model).collect();
+        prices.sort_by_key(|price| price.effective);
+        prices.iter().rev().find(|price| price.effective <= day).or(prices.first()).copied()
+    }
+
+    /// What `usage` of `model` cost at `at`, in unix milliseconds, in US CENTS. Nothing if the table has no price for the model
+    pub fn cost_in_cents(&self, model: GptModel, usage: &Usage, at: i64) -> f32 {
+        let price = match self.price(model, at) { Some(price) => price, None => return 0.0 };
+        let dollars = (usage.prompt_tokens as f32 * price.prompt_per_1k / 1000.0) // cost per 1000 / 1000 = cost per 1 => cost per 1 * tokens = cost of tokens
+            + (usage.completion_tokens as f32 * price.completion_per_1k / 1000.0);
+        dollars * 100.0
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Serialize, Deserialize};
 
 use crate::GptModel;
 
-use super::ChatCompletionResponse;
+use super::{ChatCompletionResponse, Tags};
 
 
 /// An individual Query, representing a prompt-completion event, and its metadata <br>
@@ -15,7 +15,7 @@ pub struct Query {
     pub cost: f32,
     /// The response given to this query's prompt field. `response` holds the metrics which are tracked in a running total in this OpenAIAccount's `bill`
     pub response: ChatCompletionResponse,
-    /// The time it took from sending this Query's prompt, to receiving this Query's response.
+    /// The time it took from sending this Query's prompt, to receiving this Query's response, in MILLISECONDS.
     pub process_time: u64,
     /// Model used to generate the response
     pub model: GptModel,
@@ -24,6 +24,15 @@ pub struct Query {
     pub query_type: QueryType,
     pub temperature: f32,
     pub from_cache: bool,
+    /// Unix time in milliseconds at which the Query was put in the cache. `None` until it is cached, and for Queries cached before entries were timestamped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_at: Option<i64>,
+    /// Unix time in milliseconds of the latest cache hit on this Query, used for LRU eviction (see `CachePolicy`). Not kept by every backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<i64>,
+    /// Who or what the completion is attributed to, see `Tags`. For a Query served from the cache, those of the call it was served to
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
 }
 
 impl Query {
@@ -35,9 +44,11 @@ impl Query {
 
 /// The type of request response that occured for this query. A prompt completion involved Chat Completion from a prompt, whereas a PDF summary is generated from PDF. <br>
 /// The key in the cache for a prompt completion is the prompt, whereas the key for a PdfCompletion is the pdf's filename, which should always match its storage name on disc.
-#[derive(Clone, Debug, Serialize, Deserialize, Copy)]
+/// <br> A Conversation query is one turn of a `Conversation`, keyed by the conversation's title and a hash of its history.
+#[derive(Clone, Debug, Serialize, Deserialize, Copy, PartialEq, Eq, Hash)]
 pub enum QueryType {
     PromptCompletion,
     PdfCompletion,
-    MetaCompletion
+    MetaCompletion,
+    Conversation,
 }
\ No newline at end of file
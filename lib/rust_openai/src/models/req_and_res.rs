@@ -10,6 +10,8 @@ pub enum MessageRole {
     user,
     system,
     assistant,
+    /// The result of a function the model asked to call, sent back with `name` set to the function's name
+    function,
 }
 
 
@@ -1,20 +1,57 @@
-use super::{req_and_res::ChatCompletionMessage};
+use super::{req_and_res::{ChatCompletionMessage, MessageRole}, gpt_models::GptModel};
 
 use {
     serde::{Serialize,Deserialize},
-    std::collections::HashMap
+    indexmap::IndexMap
 };
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<ChatCompletionMessage>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub functions: Option<Vec<Function>>,
+    /// `"auto"` (the default when `functions` are sent) lets the model decide whether to call one, `"none"` forbids it
     #[serde(skip_serializing_if = "Option::is_none")]
     pub function_call: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
+    /// The most tokens the completion may use. `None` lets it fill what the prompt leaves of the context window, which is also what `OpenAIAccount::estimate()` and `Budgets` then count it as costing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// When `Some(true)`, OpenAI answers with server-sent events carrying `ChatCompletionChunk`s instead of a single `ChatCompletionResponse`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+impl ChatCompletionRequest {
+    /// A request sending `content` as the single user message, without functions or streaming, as every prompt and battery completion does
+    pub fn from_prompt(model: GptModel, content: String, temperature: f32) -> ChatCompletionRequest {
+        ChatCompletionRequest {
+            model: model.to_string(),
+            messages: vec![ChatCompletionMessage { role: MessageRole::user, content: Some(content), name: None, function_call: None }],
+            functions: None,
+            function_call: None,
+            temperature: Some(temperature),
+            max_tokens: None,
+            stream: None,
+            stream_options: None,
+        }
+    }
+
+    /// Cap the completion at `max_tokens`, or lift the cap with `None`
+    pub fn with_max_tokens(mut self, max_tokens: Option<u32>) -> ChatCompletionRequest {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+/// Options only valid alongside `stream: Some(true)`. <br> `include_usage` asks OpenAI to send a final chunk holding the `Usage` of the whole completion, which is what lets a streamed completion be billed like a blocking one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StreamOptions {
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,7 +61,7 @@ pub struct ChatCompletionChoice {
     pub finish_reason: FinishReason,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Function {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -33,39 +70,40 @@ pub struct Function {
     pub parameters: Option<FunctionParameters>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum JSONSchemaType {
     Object,
     Number,
+    Integer,
     String,
     Array,
     Null,
     Boolean,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct JSONSchemaDefine {
-    #[serde(rename = "type")]
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
     pub schema_type: Option<JSONSchemaType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
     pub enum_values: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub properties: Option<HashMap<String, Box<JSONSchemaDefine>>>,
+    pub properties: Option<IndexMap<String, Box<JSONSchemaDefine>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub required: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub items: Option<Box<JSONSchemaDefine>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FunctionParameters {
     #[serde(rename = "type")]
     pub schema_type: JSONSchemaType,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub properties: Option<HashMap<String, Box<JSONSchemaDefine>>>,
+    pub properties: Option<IndexMap<String, Box<JSONSchemaDefine>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub required: Option<Vec<String>>,
 }
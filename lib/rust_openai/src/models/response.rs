@@ -3,7 +3,7 @@ use {
     serde::{Serialize,Deserialize},
     std::collections::HashMap,
     super::req_and_res,
-    req_and_res::{ChatCompletionMessage}, gpt_models::GptModel
+    req_and_res::{ChatCompletionMessage}, gpt_models::GptModel, pricing::PricingTable
 };
 
 
@@ -20,30 +20,10 @@ pub struct ChatCompletionResponse {
 
 impl ChatCompletionResponse {
     
-    /// Return cost in CENTS given the model used
+    /// Return cost in CENTS given the model used, at today's built-in price
+    #[deprecated(note = "use `OpenAIAccount::pricing().cost_in_cents()`, which honours the account's pricing file and the date the query ran")]
     pub fn cost(&self, model: &GptModel) -> f32 {
-        //usage: &Usage, model: &GptModel
-        let usage = &self.usage;
-        let (prompt_cost_factor, completion_cost_factor) = match model {
-            GptModel::Gpt35Turbo => (0.0015, 0.002), //4k
-            GptModel::Gpt35Turbo16k => (0.003, 0.004), //16k
-
-            GptModel::Gpt35Turbo0613 => (0.0015, 0.002), // 4k
-
-            GptModel::Gpt4 => (0.03, 0.06),
-            GptModel::Gpt40314 => (0.03, 0.06),
-            GptModel::Gpt40613 => (0.03, 0.06),
-
-            GptModel::Gpt432k => (0.06, 0.12),
-            GptModel::Gpt432k0314 => (0.06, 0.12),
-        };
-
-        { (
-            ( usage.prompt_tokens as f32  * prompt_cost_factor/ 1000.0 ) 
-            + 
-            ( usage.completion_tokens as f32 * completion_cost_factor/1000.0 )
-        ) as f32 * 100.0} // As cents 
-
+        PricingTable::default().cost_in_cents(*model, &self.usage, chrono::Utc::now().timestamp_millis())
     }
 }
 
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+
+/// How `OpenAIAccount::post` retries requests that failed with a 429, a 5xx, a timeout or a dropped connection. <br>
+/// The wait before retry `n` (counting from 0) is `base_delay * 2^n`, capped at `max_delay`, with up to half of it randomized away when `jitter` is set so that parallel jobs don't retry in lockstep.
+/// <br> A `Retry-After` (or OpenAI's `retry-after-ms`) sent by the server takes precedence over the computed wait.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt. `0` disables retrying.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+
+    /// Never retry: every failure is returned on the first attempt
+    pub fn none() -> RetryPolicy {
+        RetryPolicy { max_retries: 0, ..Default::default() }
+    }
+
+    /// The time to wait before retry number `attempt` (0 for the first retry)
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after { return retry_after.min(self.max_delay) }
+
+        let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        if !self.jitter { return exponential }
+
+        let keep = rand::random::<f64>() * 0.5 + 0.5;
+        exponential.mul_f64(keep)
+    }
+}
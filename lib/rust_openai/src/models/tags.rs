@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use super::budget::Membership;
+use crate::batteries::Battery;
+
+
+/// What a completion is attributed to, e.g. the user, library and job that asked for it, so what it cost can be billed to them afterwards. <br>
+/// Tags are kept with the `Query`, in the ledger and in the `tags` column of the `query_cache` table. Attach them with `OpenAIAccount::with_tags()`, and break spending down by one of them with `Ledger::by_tag()`.
+/// ```
+/// # use rust_openai::models::Tags;
+/// # use rust_openai::Battery;
+/// let tags = Tags::new().user("42").library("lib-7").job("job-3").battery(Battery::CompleteVoynich).with("team", "research");
+/// assert_eq!(tags.get(Tags::USER), Some("42"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Tags(BTreeMap<String, String>);
+
+impl Tags {
+
+    pub const USER: &'static str = "user";
+    pub const LIBRARY: &'static str = "library";
+    pub const JOB: &'static str = "job";
+    pub const DOCUMENT: &'static str = "document";
+    pub const BATTERY: &'static str = "battery";
+    /// The `Membership` tier of the user, which picks their default limits in `Budgets`
+    pub const MEMBERSHIP: &'static str = "membership";
+
+    pub fn new() -> Tags {
+        Tags::default()
+    }
+
+    /// Tag with `key` set to `value`, replacing any value it had
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Tags {
+        self.0.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn user(self, id: impl Into<String>) -> Tags {
+        self.with(Tags::USER, id)
+    }
+
+    pub fn library(self, id: impl Into<String>) -> Tags {
+        self.with(Tags::LIBRARY, id)
+    }
+
+    pub fn job(self, id: impl Into<String>) -> Tags {
+        self.with(Tags::JOB, id)
+    }
+
+    pub fn document(self, id: impl Into<String>) -> Tags {
+        self.with(Tags::DOCUMENT, id)
+    }
+
+    /// Tagged with the battery's prompt stamp, see `Battery::as_prompt_stamp()`
+    pub fn battery(self, battery: Battery) -> Tags {
+        self.with(Tags::BATTERY, battery.as_prompt_stamp())
+    }
+
+    pub fn membership(self, membership: Membership) -> Tags {
+        self.with(Tags::MEMBERSHIP, format!("{membership:?}"))
+    }
+
+    /// `key` set to `value`, unless it is already set
+    pub(crate) fn or_with(self, key: &str, value: impl Into<String>) -> Tags {
+        if self.0.contains_key(key) { self } else { self.with(key, value) }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
@@ -0,0 +1,101 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::error::{Error, Result};
+
+
+/// `path` with `.{extension}` appended, e.g. `cache.json.journal` next to `cache.json`
+pub(crate) fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{extension}"));
+    PathBuf::from(name)
+}
+
+/// Write `value` as pretty JSON to a temporary file next to `path`, flush it to disk, and rename it over `path`. A crash leaves either the old file or the new one, never half of either
+pub(crate) fn write_json_atomic<T: Serialize>(path: &Path, value: &T, what: &str) -> Result<()> {
+    let temp = sibling(path, "tmp");
+    let mut file = fs::File::create(&temp).map_err(Error::io(&temp))?;
+    serde_json::to_writer_pretty(&mut file, value).map_err(Error::json(format!("{what} to {}", path.display())))?;
+    file.flush().map_err(Error::io(&temp))?;
+    file.sync_all().map_err(Error::io(&temp))?;
+    fs::rename(&temp, path).map_err(Error::io(path))
+}
+
+/// Every line of the JSONL file at `path`, oldest first, or nothing if there is no such file. <br>
+/// A last line without its newline, cut short by a crash, is skipped, and left for `repair_tail()` to drop or complete. Any other line that cannot be parsed as a `T` is real data gone bad, and fails the read
+/// # Errors
+/// `Error::Io` if the file cannot be read, and `Error::Corrupt` if a line other than a torn last one cannot be parsed
+pub(crate) fn read_jsonl<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::Io { path: path.to_path_buf(), source: e }),
+    };
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut values = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match serde_json::from_str::<T>(line) {
+            Ok(value) => values.push(value),
+            Err(_) if index + 1 == lines.len() && !line.ends_with('\n') => break,
+            Err(e) => return Err(Error::Corrupt { path: path.to_path_buf(), message: format!("line {}: {e}", index + 1) }),
+        }
+    }
+    Ok(values)
+}
+
+/// Make the JSONL file at `path` safe to append to, under its exclusive lock. <br>
+/// A crash while appending may leave a last line without its newline, which readers skip, but which the next line appended would run into, corrupting both. Such a line is completed if it parses as a `T`, and cut off otherwise
+pub(crate) fn repair_tail<T: DeserializeOwned>(path: &Path) -> Result<()> {
+    let mut file = match fs::OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::Io { path: path.to_path_buf(), source: e }),
+    };
+    let len = file.metadata().map_err(Error::io(path))?.len();
+    if len == 0 { return Ok(()) }
+    let mut last = [0u8];
+    file.seek(SeekFrom::Start(len - 1)).and_then(|_| file.read_exact(&mut last)).map_err(Error::io(path))?;
+    if last[0] == b'\n' { return Ok(()) }
+
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_end(&mut bytes)).map_err(Error::io(path))?;
+    let start = bytes.iter().rposition(|b| *b == b'\n').map_or(0, |newline| newline + 1);
+    if serde_json::from_slice::<T>(&bytes[start..]).is_ok() {
+        file.seek(SeekFrom::End(0)).and_then(|_| file.write_all(b"\n")).map_err(Error::io(path))?;
+    } else {
+        println!("🩹 Dropping the last line of {}, cut short by a crash", path.display());
+        file.set_len(start as u64).map_err(Error::io(path))?;
+    }
+    file.sync_data().map_err(Error::io(path))
+}
+
+/// An advisory lock on `{path}.lock`, which every process sharing `path` takes before reading or writing it, released when dropped. <br>
+/// The lock is taken on a file of its own because the files it guards are replaced by renaming, and a lock on a replaced file guards nothing.
+pub(crate) struct FileLock {
+    _file: fs::File,
+}
+
+impl FileLock {
+
+    /// Wait until no other process holds any lock on `path`, then lock it to write
+    pub(crate) fn exclusive(path: &Path) -> Result<FileLock> {
+        FileLock::acquire(path, fs2::FileExt::lock_exclusive)
+    }
+
+    /// Wait until no other process is writing `path`, then lock it to read
+    pub(crate) fn shared(path: &Path) -> Result<FileLock> {
+        FileLock::acquire(path, fs2::FileExt::lock_shared)
+    }
+
+    fn acquire(path: &Path, lock: fn(&fs::File) -> io::Result<()>) -> Result<FileLock> {
+        let lock_path = sibling(path, "lock");
+        let file = fs::OpenOptions::new().create(true).write(true).truncate(false).open(&lock_path).map_err(Error::io(&lock_path))?;
+        lock(&file).map_err(Error::io(&lock_path))?;
+        Ok(FileLock { _file: file })
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::header::HeaderMap;
+
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Client-side limits applied before a request is sent. `None` leaves that dimension unlimited.
+/// <br> These should be set at or slightly below the limits of the account's OpenAI tier, so that bursts queue locally instead of being answered with 429s.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+struct RateState {
+    /// Requests sent in the last minute, with the tokens estimated for each
+    window: VecDeque<(Instant, u32)>,
+    /// Set when the server reported an exhausted limit, or answered with a 429 carrying a wait
+    blocked_until: Option<Instant>,
+}
+
+/// Sliding-window request-per-minute and token-per-minute limiter. <br>
+/// Clones share their state, so every clone of an `OpenAIAccount` draws from the same budget.
+/// <br> Besides the configured `RateLimits`, the limiter honours the `x-ratelimit-remaining-*` / `x-ratelimit-reset-*` headers OpenAI sends with every response, pausing until the reset when a remaining count hits zero.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    state: Arc<Mutex<RateState>>,
+}
+
+impl RateLimiter {
+
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter { limits, ..Default::default() }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Wait until a request estimated at `tokens` fits within the limits, then record it
+    pub async fn acquire(&self, tokens: u32) {
+        loop {
+            let wait = self.try_acquire(tokens);
+            match wait {
+                None => return,
+                Some(wait) => {
+                    println!("⏳ Rate limit reached, waiting {:.1}s", wait.as_secs_f32());
+                    tokio::time::sleep(wait).await
+                },
+            }
+        }
+    }
+
+    /// Records the request and returns `None` if it fits, otherwise returns how long to wait before trying again
+    fn try_acquire(&self, tokens: u32) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+
+        if let Some(until) = state.blocked_until {
+            if until > now { return Some(until - now) }
+            state.blocked_until = None;
+        }
+
+        while let Some((sent, _)) = state.window.front() {
+            if now.duration_since(*sent) >= WINDOW { state.window.pop_front(); } else { break }
+        }
+
+        let requests = state.window.len() as u32;
+        let used_tokens: u32 = state.window.iter().map(|(_, tokens)| tokens).sum();
+        let over_requests = matches!(self.limits.requests_per_minute, Some(rpm) if requests >= rpm);
+        // A single request larger than the whole budget is let through once the window is empty, rather than waiting forever
+        let over_tokens = matches!(self.limits.tokens_per_minute, Some(tpm) if used_tokens + tokens > tpm && !state.window.is_empty());
+
+        if over_requests || over_tokens {
+            let oldest = state.window.front().map(|(sent, _)| *sent).unwrap_or(now);
+            return Some((oldest + WINDOW).saturating_duration_since(now).max(Duration::from_millis(10)))
+        }
+
+        state.window.push_back((now, tokens));
+        None
+    }
+
+    /// Hold every request back for `wait`, e.g. after a 429
+    pub fn pause_for(&self, wait: Duration) {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let until = Instant::now() + wait;
+        if state.blocked_until.is_none_or(|blocked| blocked < until) { state.blocked_until = Some(until) }
+    }
+
+    /// Read OpenAI's `x-ratelimit-*` headers, pausing until the reported reset if either remaining count is exhausted
+    pub fn observe(&self, headers: &HeaderMap) {
+        for (remaining, reset) in [
+            ("x-ratelimit-remaining-requests", "x-ratelimit-reset-requests"),
+            ("x-ratelimit-remaining-tokens", "x-ratelimit-reset-tokens"),
+        ] {
+            let remaining = headers.get(remaining).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+            if remaining != Some(0) { continue }
+            if let Some(wait) = headers.get(reset).and_then(|v| v.to_str().ok()).and_then(parse_reset_duration) {
+                self.pause_for(wait)
+            }
+        }
+    }
+}
+
+/// The wait a server asked for: `retry-after-ms` (OpenAI), then `Retry-After` in seconds, then the `x-ratelimit-reset-*` of whichever limit is exhausted.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms / 1000.0))
+    }
+    if let Some(secs) = header("retry-after").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs))
+    }
+    ["requests", "tokens"].iter()
+        .filter(|limit| header(&format!("x-ratelimit-remaining-{limit}")) == Some("0"))
+        .filter_map(|limit| header(&format!("x-ratelimit-reset-{limit}")).and_then(parse_reset_duration))
+        .max()
+}
+
+/// Parse OpenAI's reset durations, which look like `"20ms"`, `"1s"`, `"6m0s"` or `"1h2m3.5s"`
+pub fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.trim().chars().peekable();
+    let mut parsed_any = false;
+
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' { number.push(c); continue }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        let seconds = match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => { chars.next(); amount / 1000.0 },
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+        total += seconds;
+        parsed_any = true;
+    }
+    // A bare number is taken as seconds
+    if !number.is_empty() { total += number.parse::<f64>().ok()?; parsed_any = true; }
+
+    if parsed_any { Some(Duration::from_secs_f64(total)) } else { None }
+}
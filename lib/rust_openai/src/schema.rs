@@ -0,0 +1,162 @@
+use indexmap::IndexMap;
+
+use crate::models::request::{FunctionParameters, JSONSchemaDefine, JSONSchemaType};
+
+
+/// A Rust type that knows its JSON schema, so that the shape asked of GPT (in a battery prompt, or as the parameters of a tool) and the struct its answer is deserialized into cannot drift apart. <br>
+/// Implemented for strings, numbers, booleans, `Vec<T>` (an array of `T`) and `Option<T>` (a `T` that is not required). Structs get it by being declared inside `json_schema!`.
+pub trait JsonSchema {
+    /// Whether a field of this type goes in its object's `required` list
+    const REQUIRED: bool = true;
+
+    fn schema() -> JSONSchemaDefine;
+
+    /// The schema as the `parameters` of a `Function`, for structs used as tool arguments
+    fn parameters() -> FunctionParameters {
+        let schema = Self::schema();
+        FunctionParameters { schema_type: schema.schema_type.unwrap_or(JSONSchemaType::Object), properties: schema.properties, required: schema.required }
+    }
+
+    /// The schema rendered as the commented JSON template used in battery prompts, see `template()`
+    fn template() -> String {
+        template(&Self::schema())
+    }
+}
+
+fn primitive(schema_type: JSONSchemaType) -> JSONSchemaDefine {
+    JSONSchemaDefine { schema_type: Some(schema_type), ..Default::default() }
+}
+
+macro_rules! impl_primitive {
+    ($schema_type:expr => $($ty:ty),*) => {
+        $( impl JsonSchema for $ty { fn schema() -> JSONSchemaDefine { primitive($schema_type) } } )*
+    };
+}
+
+impl_primitive!(JSONSchemaType::String => String, str, char);
+impl_primitive!(JSONSchemaType::Number => f32, f64);
+impl_primitive!(JSONSchemaType::Integer => i8, i16, i32, i64, u8, u16, u32, u64, usize, isize);
+impl_primitive!(JSONSchemaType::Boolean => bool);
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn schema() -> JSONSchemaDefine {
+        JSONSchemaDefine { schema_type: Some(JSONSchemaType::Array), items: Some(Box::new(T::schema())), ..Default::default() }
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    const REQUIRED: bool = false;
+    fn schema() -> JSONSchemaDefine {
+        T::schema()
+    }
+}
+
+/// Build an object schema from `(field name, field schema, required)`, keeping the declaration order
+#[doc(hidden)]
+pub fn object(fields: Vec<(&str, JSONSchemaDefine, bool)>) -> JSONSchemaDefine {
+    let required = fields.iter().filter(|(_, _, required)| *required).map(|(name, _, _)| name.to_string()).collect();
+    let properties: IndexMap<String, Box<JSONSchemaDefine>> = fields.into_iter().map(|(name, schema, _)| (name.to_string(), Box::new(schema))).collect();
+    JSONSchemaDefine { schema_type: Some(JSONSchemaType::Object), properties: Some(properties), required: Some(required), ..Default::default() }
+}
+
+/// Join the lines of a doc comment into a single description
+#[doc(hidden)]
+pub fn description(lines: &[&str]) -> Option<String> {
+    let description = lines.iter().map(|line| line.trim()).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ");
+    if description.is_empty() { None } else { Some(description) }
+}
+
+/// Declare a struct whose JSON schema is generated from its fields, implementing `JsonSchema` alongside serde's `Serialize` and `Deserialize`. <br>
+/// Field names become property names, field types their schemas, `Option` fields are left out of `required`, and each field's doc comment becomes its description.
+/// <br> Fields may only carry doc comments, since any other attribute (like `#[serde(rename)]`) would make the schema and the parser disagree. The crate using the macro must depend on `serde`.
+/// ```
+/// # use rust_openai::json_schema;
+/// # use rust_openai::schema::JsonSchema;
+/// json_schema! {
+///     pub struct PageArgs {
+///         /// 1-indexed page number
+///         pub page: u32,
+///         pub highlight: Option<String>,
+///     }
+/// }
+///
+/// let parameters = PageArgs::parameters();
+/// assert_eq!(parameters.required, Some(vec!["page".to_string()]));
+/// assert_eq!(parameters.properties.unwrap()["page"].description.as_deref(), Some("1-indexed page number"));
+/// ```
+#[macro_export]
+macro_rules! json_schema {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[doc = $doc:literal])*
+                $field_vis:vis $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(::serde::Serialize, ::serde::Deserialize)]
+        $vis struct $name {
+            $(
+                $(#[doc = $doc])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::schema::JsonSchema for $name {
+            fn schema() -> $crate::models::request::JSONSchemaDefine {
+                $crate::schema::object(vec![
+                    $( (
+                        stringify!($field).trim_start_matches("r#"),
+                        $crate::models::request::JSONSchemaDefine {
+                            description: $crate::schema::description(&[$($doc),*]),
+                            ..<$ty as $crate::schema::JsonSchema>::schema()
+                        },
+                        <$ty as $crate::schema::JsonSchema>::REQUIRED,
+                    ) ),*
+                ])
+            }
+        }
+    };
+}
+
+/// Render a schema as the commented JSON template the battery prompts ask GPT to fill in, e.g.
+/// ```json
+/// {
+///     "title": string,
+///     "authors": string[], /* Full names, in the order listed */
+/// }
+/// ```
+pub fn template(schema: &JSONSchemaDefine) -> String {
+    render(schema, 0)
+}
+
+fn render(schema: &JSONSchemaDefine, depth: usize) -> String {
+    match schema.schema_type {
+        Some(JSONSchemaType::Object) => {
+            let indent = "    ".repeat(depth + 1);
+            let properties = schema.properties.clone().unwrap_or_default();
+            let required = schema.required.clone().unwrap_or_default();
+
+            let mut out = String::from("{\n");
+            for (name, field) in &properties {
+                let mut comment = field.description.clone().unwrap_or_default();
+                if !required.contains(name) { comment = format!("(optional) {comment}").trim_end().to_string() }
+                let comment = if comment.is_empty() { String::new() } else { format!(" /* {comment} */") };
+                out.push_str(&format!("{indent}\"{name}\": {},{comment}\n", render(field, depth + 1)));
+            }
+            out.push_str(&format!("{}}}", "    ".repeat(depth)));
+            out
+        },
+        Some(JSONSchemaType::Array) => match &schema.items {
+            Some(items) => format!("{}[]", render(items, depth)),
+            None => "any[]".to_string(),
+        },
+        Some(JSONSchemaType::String) => "string".to_string(),
+        Some(JSONSchemaType::Number) | Some(JSONSchemaType::Integer) => "number".to_string(),
+        Some(JSONSchemaType::Boolean) => "boolean".to_string(),
+        Some(JSONSchemaType::Null) => "null".to_string(),
+        None => "any".to_string(),
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::models::{BudgetUsage, Budgets, Tags};
+
+
+/// A reservation not settled within this long, e.g. that of a `CompletionStream` dropped before it finished, is let go
+const HELD_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// What the users, libraries and jobs with budgets have spent, and what requests in flight hold against their limits, shared between clones of an `OpenAIAccount`. <br>
+/// Spending is read from the ledger once, then from the lines appended since the last check, whoever appended them, so budgets still hold across processes sharing a ledger without reading all of it for every request.
+/// A request checked against the budgets reserves what it may cost at most until its Query is recorded in the ledger, so concurrent requests are checked against each other too.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Spending {
+    state: Arc<Mutex<SpendingState>>,
+}
+
+#[derive(Debug, Default)]
+struct SpendingState {
+    /// How far the ledger has been read, in bytes
+    offset: u64,
+    /// `(at, cost)` of every completion paid for, by `(tag, value)` of the users, libraries and jobs it is attributed to
+    spent: HashMap<(String, String), Vec<(i64, f32)>>,
+    reservations: Vec<Reservation>,
+}
+
+#[derive(Debug)]
+struct Reservation {
+    /// The cache key of the request, which its Query is recorded under
+    key: String,
+    /// `(tag, value)` of each budget the reservation holds against
+    held: Vec<(String, String)>,
+    cents: f32,
+    since: Instant,
+}
+
+impl Spending {
+
+    /// Check a request that may cost up to `cents`, cached at `key` and attributed to `tags`, against `budgets`, and reserve `cents` for it if it fits
+    /// # Errors
+    /// `Error::BudgetExceeded` if it could take anyone in `tags` past a limit, and `Error::Io` or `Error::Corrupt` if the ledger cannot be read
+    pub(crate) fn reserve(&self, ledger: &Ledger, budgets: &Budgets, tags: &Tags, key: &str, cents: f32) -> Result<()> {
+        let mut state = self.state();
+        state.catch_up(ledger)?;
+        let usages = state.usage(budgets, tags, chrono::Utc::now().timestamp_millis());
+        for usage in &usages {
+            if usage.remaining_after(cents) < 0.0 {
+                return Err(Error::BudgetExceeded { tag: usage.tag.clone(), value: usage.value.clone(), period: usage.limit.period.to_string(), limit: usage.limit.cents, spent: usage.spent, reserved: usage.reserved, estimate: cents })
+            }
+            if usage.spent + usage.reserved + cents > budgets.soft_limit * usage.limit.cents {
+                println!("⚠️  {} {} has spent ¢{:.2} of its ¢{:.2} {}, ¢{:.2} is held for requests in flight, and this request may cost up to ¢{:.2}", usage.tag, usage.value, usage.spent, usage.limit.cents, usage.limit.period, usage.reserved, cents);
+            }
+        }
+        let held = usages.into_iter().map(|usage| (usage.tag, usage.value)).collect();
+        state.reservations.push(Reservation { key: key.to_string(), held, cents, since: Instant::now() });
+        Ok(())
+    }
+
+    /// What each user, library and job in `tags` has spent in the current period of its limit, and holds for requests in flight
+    /// # Errors
+    /// `Error::Io` or `Error::Corrupt` if the ledger cannot be read
+    pub(crate) fn usage(&self, ledger: &Ledger, budgets: &Budgets, tags: &Tags) -> Result<Vec<BudgetUsage>> {
+        let mut state = self.state();
+        state.catch_up(ledger)?;
+        Ok(state.usage(budgets, tags, chrono::Utc::now().timestamp_millis()))
+    }
+
+    /// Let go of the reservation of the request cached at `key`, which was recorded in the ledger. If recording it failed, `unrecorded` is counted here instead, so what it cost still counts against the budgets of this account
+    pub(crate) fn settle(&self, key: &str, unrecorded: Option<&LedgerEntry>) {
+        let mut state = self.state();
+        state.release(key);
+        if let Some(entry) = unrecorded { state.add(entry) }
+    }
+
+    /// Let go of the reservation of the request cached at `key`, which failed, so nothing was paid for it
+    pub(crate) fn release(&self, key: &str) {
+        self.state().release(key);
+    }
+
+    /// Never held across an `.await`
+    fn state(&self) -> MutexGuard<'_, SpendingState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SpendingState {
+
+    /// Count the completions appended to the ledger since it was last read
+    fn catch_up(&mut self, ledger: &Ledger) -> Result<()> {
+        let tail = ledger.tail(self.offset)?;
+        if tail.restarted { self.spent.clear() }
+        for entry in &tail.entries { self.add(entry) }
+        self.offset = tail.end;
+        Ok(())
+    }
+
+    fn add(&mut self, entry: &LedgerEntry) {
+        if entry.cache_hit { return }
+        for tag in [Tags::USER, Tags::LIBRARY, Tags::JOB] {
+            if let Some(value) = entry.tags.get(tag) {
+                self.spent.entry((tag.to_string(), value.to_string())).or_default().push((entry.at, entry.cost));
+            }
+        }
+    }
+
+    fn release(&mut self, key: &str) {
+        if let Some(index) = self.reservations.iter().position(|reservation| reservation.key == key) {
+            self.reservations.remove(index);
+        }
+    }
+
+    fn usage(&mut self, budgets: &Budgets, tags: &Tags, now: i64) -> Vec<BudgetUsage> {
+        self.reservations.retain(|reservation| reservation.since.elapsed() < HELD_FOR);
+        budgets.limits_of(tags).into_iter().map(|budget| {
+            let since = budget.limit.period.start(now);
+            let id = (budget.tag, budget.value);
+            let spent = self.spent.get(&id).map_or(0.0, |costs| costs.iter().filter(|(at, _)| *at >= since).fold(0.0, |spent, (_, cost)| spent + cost));
+            let reserved = self.reservations.iter().filter(|reservation| reservation.held.contains(&id)).fold(0.0, |reserved, reservation| reserved + reservation.cents);
+            BudgetUsage { tag: id.0, value: id.1, limit: budget.limit, spent, reserved }
+        }).collect()
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream;
+
+use crate::error::{Error, Result};
+use crate::flight::FlightGuard;
+use crate::models::chunk::ChatCompletionChunk;
+use crate::models::req_and_res::{FunctionCall, Usage};
+use crate::models::response::{ChatCompletionChoice, FinishReason};
+use crate::models::*;
+
+
+type ByteStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send>>;
+
+/// The bookkeeping needed to turn a finished stream into a `Query`, carried alongside the stream so `OpenAIAccount::finish_stream` can cache and bill it.
+#[derive(Clone, Debug)]
+pub(crate) struct PendingQuery {
+    pub cache_key: String,
+    pub prompt: String,
+    pub query_type: QueryType,
+    pub model: GptModel,
+    pub temperature: f32,
+    pub tags: Tags,
+    pub start_time: std::time::Instant,
+}
+
+/// A streamed chat completion. Yields the content deltas as they arrive from OpenAI, while accumulating them into a full `ChatCompletionResponse`. <br>
+/// Obtained from `OpenAIAccount::get_completion_stream` or `OpenAIAccount::apply_battery_to_pdf_stream`, drained with `StreamExt::next`, and then handed back to `OpenAIAccount::finish_stream` which caches and bills the completed `Query`.
+/// <br> If the prompt was already in the cache, the cached content is yielded as a single delta and nothing is sent to OpenAI.
+pub struct CompletionStream {
+    inner: ByteStream,
+    /// Bytes received but not yet split into complete `data:` lines
+    buffer: Vec<u8>,
+    /// Content deltas parsed but not yet yielded
+    pending: VecDeque<String>,
+    done: bool,
+    /// Set when the connection or a chunk failed, so a truncated completion is never cached
+    failed: bool,
+
+    id: String,
+    created: i64,
+    response_model: String,
+    content: String,
+    function_call: Option<FunctionCall>,
+    finish_reason: Option<FinishReason>,
+    usage: Option<Usage>,
+
+    pub(crate) query: Option<PendingQuery>,
+    /// The cache key and Query a cached stream replays
+    pub(crate) cached: Option<(String, Query)>,
+    /// Keeps the cache key in flight until the Query is cached by `finish_stream()`, or the stream is dropped
+    pub(crate) flight: Option<FlightGuard>,
+    /// The request, in `Mode::Record`, to be recorded with the response by `finish_stream()`
+    pub(crate) recording: Option<ChatCompletionRequest>,
+}
+
+impl CompletionStream {
+
+    /// Wraps the body of a `stream: true` response. With a `read_timeout`, the stream fails with `Error::Stream` when OpenAI sends nothing for that long.
+    pub(crate) fn new(response: reqwest::Response, query: PendingQuery, read_timeout: Option<Duration>) -> CompletionStream {
+        use futures::StreamExt;
+        let bytes = response.bytes_stream().map(|bytes| bytes.map(|b| b.to_vec()).map_err(Error::Http));
+        let inner: ByteStream = match read_timeout {
+            None => Box::pin(bytes),
+            Some(read_timeout) => Box::pin(futures::stream::unfold(Some(Box::pin(bytes)), move |bytes| async move {
+                let mut bytes = bytes?;
+                match tokio::time::timeout(read_timeout, bytes.next()).await {
+                    Ok(Some(item)) => Some((item, Some(bytes))),
+                    Ok(None) => None,
+                    Err(_) => Some((Err(Error::Stream(format!("no data received for {}s", read_timeout.as_secs()))), None)),
+                }
+            })),
+        };
+        CompletionStream {
+            inner,
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            done: false,
+            failed: false,
+            id: String::new(),
+            created: 0,
+            response_model: String::new(),
+            content: String::new(),
+            function_call: None,
+            finish_reason: None,
+            usage: None,
+            query: Some(query),
+            cached: None,
+            flight: None,
+            recording: None,
+        }
+    }
+
+    /// Hold `flight` until this stream is finished or dropped
+    pub(crate) fn holding(mut self, flight: FlightGuard) -> CompletionStream {
+        self.flight = Some(flight);
+        self
+    }
+
+    /// Record `req` with the response once the stream is finished
+    pub(crate) fn recording(mut self, req: ChatCompletionRequest) -> CompletionStream {
+        self.recording = Some(req);
+        self
+    }
+
+    /// A stream that replays a recorded response as one delta, without touching the network, and is then cached and billed like a live one
+    pub(crate) fn from_fixture(response: ChatCompletionResponse, query: PendingQuery) -> CompletionStream {
+        let choice = response.choices.first();
+        let content = choice.and_then(|choice| choice.message.content.clone()).unwrap_or_default();
+        CompletionStream {
+            inner: Box::pin(futures::stream::empty()),
+            buffer: Vec::new(),
+            pending: VecDeque::from([content.clone()]),
+            done: true,
+            failed: false,
+            id: response.id.clone(),
+            created: response.created,
+            response_model: response.model.clone(),
+            content,
+            function_call: choice.and_then(|choice| choice.message.function_call.clone()),
+            finish_reason: Some(choice.map_or(FinishReason::stop, |choice| choice.finish_reason.clone())),
+            usage: Some(response.usage.clone()),
+            query: Some(query),
+            cached: None,
+            flight: None,
+            recording: None,
+        }
+    }
+
+    /// A stream that replays a cached Query's content as one delta, without touching the network
+    pub(crate) fn from_cache(key: String, query: Query) -> CompletionStream {
+        let content = query.response.choices.first().and_then(|choice| choice.message.content.clone()).unwrap_or_default();
+        CompletionStream {
+            inner: Box::pin(futures::stream::empty()),
+            buffer: Vec::new(),
+            pending: VecDeque::from([content.clone()]),
+            done: true,
+            failed: false,
+            id: query.response.id.clone(),
+            created: query.response.created,
+            response_model: query.response.model.clone(),
+            content,
+            function_call: None,
+            finish_reason: Some(FinishReason::stop),
+            usage: Some(query.response.usage.clone()),
+            query: None,
+            cached: Some((key, query)),
+            flight: None,
+            recording: None,
+        }
+    }
+
+    /// Whether the server has sent `data: [DONE]` (or closed the connection, which fails the stream) and every delta has been yielded
+    pub fn is_finished(&self) -> bool {
+        self.done && self.pending.is_empty()
+    }
+
+    /// The content received so far
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Assemble the accumulated deltas into the response OpenAI would have sent for a blocking request.
+    /// # Errors
+    /// `Error::Usage` until the stream is finished, and `Error::Stream` if it ended on an error or without its usage. Usage is always asked for, and sent in the last chunk, so a stream without it was cut short
+    pub fn response(&self) -> Result<ChatCompletionResponse> {
+        if !self.is_finished() { return Err(Error::Usage("the stream has not yielded its last delta yet".to_string())) }
+        if self.failed { return Err(Error::Stream("the stream ended on an error".to_string())) }
+        let usage = self.usage.clone().ok_or_else(|| Error::Stream("the stream ended without its usage, so it was cut short".to_string()))?;
+        Ok(ChatCompletionResponse {
+            id: self.id.clone(),
+            object: "chat.completion".to_string(),
+            created: self.created,
+            model: self.response_model.clone(),
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatCompletionMessage {
+                    role: MessageRole::assistant,
+                    content: if self.content.is_empty() && self.function_call.is_some() { None } else { Some(self.content.clone()) },
+                    name: None,
+                    function_call: self.function_call.clone(),
+                },
+                finish_reason: self.finish_reason.clone().unwrap_or(FinishReason::null),
+            }],
+            usage,
+        })
+    }
+
+    /// Split the buffer into complete lines, and fold each `data:` line into the accumulated response
+    fn drain_lines(&mut self) -> Result<()> {
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            // Blank lines separate events, and lines starting with ':' are SSE comments
+            let data = match line.strip_prefix("data:") { Some(data) => data.trim(), None => continue };
+            if data == "[DONE]" { self.done = true; continue }
+            self.apply_chunk(data)?;
+        }
+        Ok(())
+    }
+
+    fn apply_chunk(&mut self, data: &str) -> Result<()> {
+        let chunk: ChatCompletionChunk = match serde_json::from_str(data) {
+            Ok(chunk) => chunk,
+            Err(e) => return Err(Error::Stream(format!("Could not parse chunk ({e}): {data}"))),
+        };
+        self.id = chunk.id;
+        self.created = chunk.created;
+        self.response_model = chunk.model;
+        if let Some(usage) = chunk.usage { self.usage = Some(usage) }
+
+        for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+            if let Some(reason) = choice.finish_reason { self.finish_reason = Some(reason) }
+            if let Some(call) = choice.delta.function_call {
+                let accumulated = self.function_call.get_or_insert(FunctionCall { name: None, arguments: None });
+                if let Some(name) = call.name { accumulated.name.get_or_insert_with(String::new).push_str(&name) }
+                if let Some(arguments) = call.arguments { accumulated.arguments.get_or_insert_with(String::new).push_str(&arguments) }
+            }
+            if let Some(content) = choice.delta.content {
+                if content.is_empty() { continue }
+                self.content.push_str(&content);
+                self.pending.push_back(content);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Stream for CompletionStream {
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(delta) = self.pending.pop_front() { return Poll::Ready(Some(Ok(delta))) }
+            if self.done { return Poll::Ready(None) }
+
+            match self.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(bytes))) => {
+                    self.buffer.extend_from_slice(&bytes);
+                    if let Err(e) = self.drain_lines() { self.done = true; self.failed = true; return Poll::Ready(Some(Err(e))) }
+                },
+                Poll::Ready(Some(Err(e))) => {
+                    self.done = true;
+                    self.failed = true;
+                    return Poll::Ready(Some(Err(e)))
+                },
+                Poll::Ready(None) => {
+                    // Connection closed: flush a last line that came without its trailing newline, which may be the `[DONE]`
+                    self.buffer.push(b'\n');
+                    let flushed = self.drain_lines();
+                    let closed_early = !self.done;
+                    self.done = true;
+                    if let Err(e) = flushed { self.failed = true; return Poll::Ready(Some(Err(e))) }
+                    if closed_early { self.failed = true; return Poll::Ready(Some(Err(Error::Stream("the connection closed before data: [DONE]".to_string())))) }
+                },
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::OnceLock;
+
+use tiktoken_rs::CoreBPE;
+
+use crate::error::Result;
+use crate::models::{ChatCompletionRequest, GptModel};
+
+
+/// Tokens counted locally with the byte-pair encoding of each `GptModel` family, the same `tiktoken` encoding OpenAI counts and bills with. <br>
+/// Used to check a request against the context window of its model, and to estimate what it may cost, before anything is sent. See `OpenAIAccount::estimate()`.
+/// ```
+/// # use rust_openai::tokenizer;
+/// # use rust_openai::GptModel;
+/// assert_eq!(tokenizer::count(GptModel::Gpt4, "tiktoken is great!"), 6);
+/// ```
+pub fn count(model: GptModel, text: &str) -> usize {
+    match encoding(model) {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        // Never expected, as the ranks are compiled in: overestimate rather than let a request through unchecked
+        None => text.len().div_ceil(3),
+    }
+}
+
+/// The prompt tokens OpenAI will bill for `req`: its messages with their framing, and its function definitions. <br>
+/// Messages are counted exactly as OpenAI documents for its chat models. How function definitions are laid out in the prompt is not documented, so they are counted as their JSON, which slightly overestimates them
+/// # Errors
+/// `Error::UnknownModel` if the request's model is not a `GptModel`
+pub fn count_request(req: &ChatCompletionRequest) -> Result<usize> {
+    let model = GptModel::from_string(&req.model)?;
+    // Every message is framed as <|start|>{role/name}\n{content}<|end|>\n, and the reply is primed with <|start|>assistant<|message|>
+    const PER_MESSAGE: usize = 3;
+    const PER_NAME: usize = 1;
+    const REPLY_PRIMING: usize = 3;
+
+    let mut tokens = REPLY_PRIMING;
+    for message in &req.messages {
+        tokens += PER_MESSAGE + count(model, &format!("{:?}", message.role));
+        if let Some(content) = &message.content { tokens += count(model, content) }
+        if let Some(name) = &message.name { tokens += PER_NAME + count(model, name) }
+        if let Some(call) = &message.function_call {
+            tokens += call.name.as_deref().map_or(0, |name| count(model, name));
+            tokens += call.arguments.as_deref().map_or(0, |arguments| count(model, arguments));
+        }
+    }
+    if let Some(functions) = &req.functions {
+        tokens += count(model, &serde_json::to_string(functions).unwrap_or_default());
+    }
+    Ok(tokens)
+}
+
+/// The encoding of `model`'s family, built once
+fn encoding(model: GptModel) -> Option<&'static CoreBPE> {
+    static CL100K_BASE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    use GptModel::*;
+    match model {
+        Gpt35Turbo | Gpt35Turbo16k | Gpt35Turbo0613
+        | Gpt4 | Gpt40314 | Gpt40613 | Gpt432k | Gpt432k0314 => CL100K_BASE.get_or_init(|| tiktoken_rs::cl100k_base().ok()).as_ref(),
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::error::Result;
+use crate::models::req_and_res::FunctionCall;
+use crate::models::request::{Function, FunctionParameters};
+
+
+/// Default for `ToolBox::max_rounds`
+pub const DEFAULT_MAX_ROUNDS: usize = 8;
+
+type ToolFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+/// Deserializes the arguments, then runs the handler. Failing to deserialize is kept apart from the handler's own errors, as it is reported back to the model.
+type Handler = Arc<dyn Fn(serde_json::Value) -> serde_json::Result<ToolFuture> + Send + Sync>;
+
+/// A single Rust function the model may call: the `Function` definition sent to OpenAI, and the handler run when the model calls it.
+#[derive(Clone)]
+pub struct Tool {
+    pub function: Function,
+    handler: Handler,
+}
+
+/// The set of tools offered to the model by `OpenAIAccount::converse_with_tools`. <br>
+/// Each tool is registered with a name, a description, the JSON schema of its arguments, and an async handler taking the arguments deserialized into a Rust type. The handler's `Ok` string is sent back to the model as the function's result.
+/// <br> When the model calls an unknown tool or sends arguments that do not match the schema, the error is sent back to the model as the result, so it can correct itself. Errors returned by a handler abort the exchange.
+/// ```
+/// # use rust_openai::ToolBox;
+/// # use rust_openai::models::request::{FunctionParameters, JSONSchemaDefine, JSONSchemaType};
+/// #[derive(serde::Deserialize)]
+/// struct PageArgs { page: u32 }
+///
+/// let parameters = FunctionParameters {
+///     schema_type: JSONSchemaType::Object,
+///     properties: Some(indexmap::IndexMap::from([("page".to_string(), Box::new(JSONSchemaDefine {
+///         schema_type: Some(JSONSchemaType::Number), description: Some("1-indexed page number".to_string()),
+///         enum_values: None, properties: None, required: None, items: None,
+///     }))])),
+///     required: Some(vec!["page".to_string()]),
+/// };
+///
+/// let tools = ToolBox::new().with_tool("get_page", "Returns the text of page N of the document", parameters, |args: PageArgs| async move {
+///     Ok(format!("Text of page {}", args.page))
+/// });
+/// assert_eq!(tools.functions()[0].name, "get_page");
+/// ```
+#[derive(Clone)]
+pub struct ToolBox {
+    tools: HashMap<String, Tool>,
+    /// How many times the model may call a function before the exchange is aborted with `Error::Usage`
+    pub max_rounds: usize,
+}
+
+impl Default for ToolBox {
+    fn default() -> Self {
+        ToolBox { tools: HashMap::new(), max_rounds: DEFAULT_MAX_ROUNDS }
+    }
+}
+
+impl fmt::Debug for ToolBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolBox").field("tools", &self.tools.keys().collect::<Vec<_>>()).field("max_rounds", &self.max_rounds).finish()
+    }
+}
+
+impl ToolBox {
+
+    pub fn new() -> ToolBox {
+        ToolBox::default()
+    }
+
+    pub fn with_tool<A, F, Fut>(mut self, name: &str, description: &str, parameters: FunctionParameters, handler: F) -> ToolBox
+    where
+        A: DeserializeOwned + Send + 'static,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        self.register(name, description, parameters, handler);
+        self
+    }
+
+    /// Register a tool, replacing any previous tool of the same name
+    pub fn register<A, F, Fut>(&mut self, name: &str, description: &str, parameters: FunctionParameters, handler: F)
+    where
+        A: DeserializeOwned + Send + 'static,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |arguments: serde_json::Value| {
+            let args = serde_json::from_value::<A>(arguments)?;
+            Ok(Box::pin(handler(args)) as ToolFuture)
+        });
+        let function = Function { name: name.to_string(), description: Some(description.to_string()), parameters: Some(parameters) };
+        self.tools.insert(name.to_string(), Tool { function, handler });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// The definitions sent in the `functions` field of the request, sorted by name so the request (and its cache key) is stable
+    pub fn functions(&self) -> Vec<Function> {
+        let mut functions: Vec<Function> = self.tools.values().map(|tool| tool.function.clone()).collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        functions
+    }
+
+    /// Run the tool the model asked for, returning the content of the `function` message to send back. <br>
+    /// Unknown tools and malformed arguments are reported back to the model rather than returned as errors.
+    pub async fn call(&self, call: &FunctionCall) -> Result<String> {
+        let name = call.name.as_deref().unwrap_or_default();
+        let tool = match self.tools.get(name) {
+            Some(tool) => tool,
+            None => return Ok(format!("Error: there is no function named \"{name}\"")),
+        };
+
+        let arguments = match serde_json::from_str::<serde_json::Value>(call.arguments.as_deref().unwrap_or("{}")) {
+            Ok(arguments) => arguments,
+            Err(e) => return Ok(format!("Error: the arguments are not valid JSON: {e}")),
+        };
+
+        match (tool.handler)(arguments) {
+            Ok(future) => future.await,
+            Err(e) => Ok(format!("Error: the arguments do not match the schema: {e}")),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Report what an `OpenAIAccount` spent, from its ledger. Run `cargo run --bin bill -- help` for usage.
+
+use std::fmt::Display;
+
+use chrono::NaiveDate;
+use rust_openai::client::LEDGER_FILEPATH;
+use rust_openai::ledger::Ledger;
+use rust_openai::models::Bill;
+use rust_openai::Error;
+
+
+const USAGE: &str = "\
+Usage: bill [--ledger FILE] [--since YYYY-MM-DD] [--until YYYY-MM-DD] <report>
+
+Reports:
+  total        The bill since the last reset
+  day          Spending on each day (UTC)
+  month        Spending in each month (UTC)
+  model        Spending on each model
+  tag NAME     Spending for each value of the tag NAME, e.g. user, library, job, document or battery
+
+--ledger defaults to ledger.jsonl in the working directory. --since and --until bound every report but total.";
+
+fn main() {
+    if let Err(e) = run(std::env::args().skip(1).collect()) {
+        eprintln!("❌ {e}");
+        if let Error::Usage(_) = *e { eprintln!("\n{USAGE}") }
+        std::process::exit(2);
+    }
+}
+
+fn run(args: Vec<String>) -> std::result::Result<(), Box<Error>> {
+    let mut ledger = LEDGER_FILEPATH.to_string();
+    let (mut since, mut until) = (i64::MIN, i64::MAX);
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") { positional.push(arg); continue }
+        let value = args.next().ok_or_else(|| Error::Usage(format!("{arg} needs a value")))?;
+        match arg.as_str() {
+            "--ledger" => ledger = value,
+            "--since" => since = day(&value).map_err(Error::Usage)?,
+            "--until" => until = day(&value).map_err(Error::Usage)?,
+            _ => return Err(Error::Usage(format!("unknown option {arg}")).into()),
+        }
+    }
+
+    let ledger = Ledger::new(ledger);
+    let span = since..until;
+    match (positional.first().map(String::as_str), positional.get(1)) {
+        (None | Some("help"), _) => println!("{USAGE}"),
+        (Some("total"), None) => report([("total", ledger.bill()?)]),
+        (Some("day"), None) => report(ledger.by_day(span)?),
+        (Some("month"), None) => report(ledger.by_month(span)?.into_iter().map(|(month, bill)| (month.format("%Y-%m").to_string(), bill))),
+        (Some("model"), None) => {
+            let mut models: Vec<_> = ledger.by_model(span)?.into_iter().map(|(model, bill)| (model.to_string(), bill)).collect();
+            models.sort_by(|(_, a), (_, b)| b.cost.total_cmp(&a.cost));
+            report(models)
+        },
+        (Some("tag"), Some(tag)) => report(ledger.by_tag(tag, span)?),
+        _ => return Err(Error::Usage(format!("unknown report: {}", positional.join(" "))).into()),
+    }
+    Ok(())
+}
+
+/// One line per group, then the sum of them
+fn report<K: Display>(groups: impl IntoIterator<Item = (K, Bill)>) {
+    let (mut cost, mut queries, mut saved) = (0.0, 0, 0.0);
+    for (group, bill) in groups {
+        println!("{group:<24}  ${:>10.4}  {:>6} queries  {:>8} tokens  {:>6} from cache, saving ${:.4}", bill.cost / 100.0, bill.query_count, bill.total_tokens, bill.cache_retrievals, bill.cache_savings / 100.0);
+        cost += bill.cost;
+        queries += bill.query_count;
+        saved += bill.cache_savings;
+    }
+    println!("{:<24}  ${:>10.4}  {queries:>6} queries  saved ${:.4}", "", cost / 100.0, saved / 100.0);
+}
+
+/// Midnight UTC of the day `value`, in unix milliseconds
+fn day(value: &str) -> std::result::Result<i64, String> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("not a YYYY-MM-DD date: {value}"))?;
+    Ok(date.and_hms_opt(0, 0, 0).map_or(0, |midnight| midnight.and_utc().timestamp_millis()))
+}
@@ -0,0 +1,127 @@
+//! Inspect the cache of an `OpenAIAccount` from the command line. Run `cargo run --bin cache -- help` for usage.
+
+use std::sync::Arc;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use rust_openai::cache::SeaOrmCache;
+use rust_openai::client::{CACHE_FILEPATH, LEDGER_FILEPATH};
+use rust_openai::models::{CacheFilter, QueryType};
+use rust_openai::{Error, GptModel, Mode, OpenAIAccountBuilder, Result};
+use sea_orm::Database;
+
+
+const USAGE: &str = "\
+Usage: cache [--cache FILE | --database URL] [--ledger FILE] [--bill FILE] <command> [options]
+
+Commands:
+  list                 List the entries picked by the filters, oldest first
+  stats [--largest N]  Sizes, hit rate, money saved, largest entries and duplicates
+  export FILE          Copy the entries picked by the filters into the cache file FILE
+  rehash               Recompute the query_key_hash of every row of the --database cache
+  rekey [--pdf-dir DIR]
+                       Move entries cached before keys covered the whole request to their request keys, finding
+                       the pdfs of battery completions in DIR (default ./pdfs). On a database, run rehash first
+
+Filters:
+  --type TYPE          PromptCompletion, PdfCompletion, MetaCompletion or Conversation
+  --model MODEL        An OpenAI model name, e.g. gpt-4
+  --since YYYY-MM-DD   Cached on or after this day (UTC)
+  --until YYYY-MM-DD   Cached before this day (UTC)
+  --min-cost CENTS
+  --max-cost CENTS
+  --tag NAME=VALUE     Attributed to VALUE for the tag NAME, e.g. user=42. Can be repeated
+
+--cache and --ledger default to cache.json and ledger.jsonl in the working directory. --database reads the cache from
+the query_cache table of a database instead, e.g. the server's DATABASE_URL. Only rehash and rekey write to the cache, and
+nothing is written to the ledger unless --bill names a bill file, which is carried into the ledger if it is not in it yet.";
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(std::env::args().skip(1).collect()).await {
+        eprintln!("❌ {e}");
+        if let Error::Usage(_) = e { eprintln!("\n{USAGE}") }
+        std::process::exit(2);
+    }
+}
+
+async fn run(args: Vec<String>) -> Result<()> {
+    let mut cache = None;
+    let mut database = None;
+    let mut ledger = LEDGER_FILEPATH.to_string();
+    let mut bill = None;
+    let mut pdf_dir = None;
+    let mut filter = CacheFilter::default();
+    let mut largest = 10;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") { positional.push(arg); continue }
+        let value = args.next().ok_or_else(|| Error::Usage(format!("{arg} needs a value")))?;
+        match arg.as_str() {
+            "--cache" => cache = Some(value),
+            "--database" => database = Some(value),
+            "--ledger" => ledger = value,
+            "--bill" => bill = Some(value),
+            "--pdf-dir" => pdf_dir = Some(value),
+            "--largest" => largest = parse(&arg, &value).map_err(Error::Usage)?,
+            "--type" => filter.query_type = Some(serde_json::from_value::<QueryType>(value.clone().into()).map_err(|_| Error::Usage(format!("unknown query type: {value}")))?),
+            "--model" => filter.model = Some(GptModel::from_string(&value)?),
+            "--since" => filter.cached_after = Some(day(&value).map_err(Error::Usage)?),
+            "--until" => filter.cached_before = Some(day(&value).map_err(Error::Usage)?),
+            "--min-cost" => filter.min_cost = Some(parse(&arg, &value).map_err(Error::Usage)?),
+            "--max-cost" => filter.max_cost = Some(parse(&arg, &value).map_err(Error::Usage)?),
+            "--tag" => {
+                let (key, value) = value.split_once('=').ok_or_else(|| Error::Usage(format!("--tag takes NAME=VALUE, not {value}")))?;
+                filter = filter.with_tag(key, value);
+            },
+            _ => return Err(Error::Usage(format!("unknown option {arg}"))),
+        }
+    }
+
+    let command = positional.first().map(String::as_str);
+    if matches!(command, None | Some("help")) { println!("{USAGE}"); return Ok(()) }
+
+    // Never ask OpenAI for anything from here
+    if cache.is_some() && database.is_some() { return Err(Error::Usage("--cache and --database cannot be used together".to_string())) }
+    let database = match database {
+        Some(url) => Some(Arc::new(SeaOrmCache::new(Database::connect(url).await?))),
+        None => None,
+    };
+    let mut builder = OpenAIAccountBuilder::new("").ledger_file(&ledger).mode(Mode::CacheOnly);
+    builder = match &database {
+        Some(database) => builder.cache_backend(database.clone()),
+        None => builder.cache_file(cache.unwrap_or_else(|| CACHE_FILEPATH.to_string())),
+    };
+    if let Some(bill) = bill { builder = builder.bill_file(bill) }
+    let account = builder.build()?;
+    match (command, positional.get(1)) {
+        (Some("list"), None) => {
+            let entries = account.list_cache(&filter).await?;
+            for (key, query) in &entries {
+                let cached_at = query.cached_at.and_then(|at| Utc.timestamp_millis_opt(at).single()).map_or("-".to_string(), |at| at.format("%Y-%m-%d %H:%M").to_string());
+                println!("{cached_at:<16}  {:<16}  {:<14}  ¢{:>8.4}  {key}", format!("{:?}", query.query_type), query.model.to_string(), query.cost);
+            }
+            println!("{} entries", entries.len());
+        },
+        (Some("stats"), None) => print!("{}", account.cache_stats(largest).await?),
+        (Some("export"), Some(path)) => { account.export_cache(&filter, path).await?; },
+        (Some("rehash"), None) => match &database {
+            Some(database) => { database.rehash().await?; },
+            None => return Err(Error::Usage("rehash works on the query_cache table, name its database with --database".to_string())),
+        },
+        (Some("rekey"), None) => { account.migrate_cache_keys(pdf_dir).await?; },
+        _ => return Err(Error::Usage(format!("unknown command: {}", positional.join(" ")))),
+    }
+    Ok(())
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: &str) -> std::result::Result<T, String> {
+    value.parse().map_err(|_| format!("{option} does not take {value}"))
+}
+
+/// Midnight UTC of the day `value`, in unix milliseconds
+fn day(value: &str) -> std::result::Result<i64, String> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("not a YYYY-MM-DD date: {value}"))?;
+    Ok(date.and_hms_opt(0, 0, 0).map_or(0, |midnight| midnight.and_utc().timestamp_millis()))
+}
@@ -14,6 +14,11 @@ use routes::{
     jobs
 };
 use sea_orm::Database;
+use rust_openai::{ApiConfig, GptModel, OpenAIAccountBuilder};
+use rust_openai::cache::SeaOrmCache;
+use rust_openai::client::{BILL_FILEPATH, LEDGER_FILEPATH};
+use rust_openai::models::Budgets;
+use std::sync::Arc;
 
 
 #[launch]
@@ -30,8 +35,27 @@ async fn rocket() -> _ {
     Err(e) => panic!("Error connecting to DB: {e}"),
     };
 
+    // One account serves every request, sharing its cache and bill. Routes attribute their completions to a user, their membership, library and job with `utils::attribution::tagged_account`, and are held to the default budgets of that membership; untagged requests are refused
+    let builder = match OpenAIAccountBuilder::from_env() {
+    Ok(builder) => builder,
+    Err(e) => panic!("Error building the OpenAI account: {e}"),
+    };
+    let openai = match builder
+        .model(GptModel::Gpt35Turbo)
+        .temperature(0.5)
+        .api_config(ApiConfig::from_env())
+        .cache_backend(Arc::new(SeaOrmCache::new(db.clone())))
+        .ledger_file(LEDGER_FILEPATH)
+        .bill_file(BILL_FILEPATH)
+        .budgets(Budgets::default())
+        .build() {
+    Ok(openai) => openai,
+    Err(e) => panic!("Error building the OpenAI account: {e}"),
+    };
+
     rocket::build()
         .manage(db)
+        .manage(openai)
         .mount("/dev", routes![rust_openai_test::test])
         .mount("/documents", routes![
                 documents::add::handler,
@@ -1,21 +1,20 @@
+use rocket::State;
+use rocket::response::Debug;
 use rocket::serde::json::Json;
-use rust_openai::Query;
+use rust_openai::{OpenAIAccount, Query};
+use rust_openai::models::{Membership, Tags};
 
 
 #[get("/rust_openai")]
-pub async fn test() -> Json<Query> {
+pub async fn test(openai: &State<OpenAIAccount>) -> Result<Json<Query>, Debug<rust_openai::Error>> {
 
-    let mut openai = rust_openai::OpenAIAccount::new(rust_openai::GptModel::Gpt35Turbo, 0.5);
+    // Billed to a user of its own, so the dev route is held to the Unpaid budgets like anyone else
     let res = openai
+        .with_tags(Tags::new().user("dev").membership(Membership::Unpaid).job("rust_openai_test"))
         .get_completion("Spell alphabet".to_string(), None)
-        .await
-        .expect("Failed to get completion");
+        .await?;
 
 
-    openai.db_insert_cache().await.expect("insertion");
-
-
-    Json(res)
+    Ok(Json(res))
 
 }
-
@@ -1,7 +1,10 @@
-use rocket::{serde::json::Json, State};
+use rocket::{response::Debug, serde::json::Json, State};
+use rust_openai::OpenAIAccount;
 use sea_orm::DatabaseConnection;
 use serde::{Serialize, Deserialize};
 
+use crate::utils::attribution::tagged_account;
+
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Request {
@@ -40,8 +43,11 @@ pub struct Response {
 
 
 #[post("/add", data = "<body>")]
-pub async fn handler(db: &State<DatabaseConnection>, body: Json<Request>) -> Json<Response> {
+pub async fn handler(db: &State<DatabaseConnection>, openai: &State<OpenAIAccount>, body: Json<Request>) -> Result<Json<Response>, Debug<rust_openai::Error>> {
+
+    // Completions run on the document are billed to its user and library, within their budgets
+    let _openai = tagged_account(db, openai, &body.user_id, &body.library_id, None).await?;
 
-    Json(todo!())
+    Ok(Json(todo!()))
 }
 
@@ -0,0 +1,22 @@
+use rust_openai::models::{Membership, Tags};
+use rust_openai::OpenAIAccount;
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+
+
+/// The shared account, with its completions attributed to the user `user_id` at the membership of their `User` row, to the library `library_id`, and to the job `job_id` if the work runs as one. <br>
+/// Every route or job asking OpenAI for anything goes through this, as the account's budgets refuse requests attributed to no user.
+pub async fn tagged_account(db: &DatabaseConnection, openai: &OpenAIAccount, user_id: &str, library_id: &str, job_id: Option<&str>) -> rust_openai::Result<OpenAIAccount> {
+    let mut tags = Tags::new().user(user_id).membership(membership(db, user_id).await?).library(library_id);
+    if let Some(job_id) = job_id { tags = tags.job(job_id) }
+    Ok(openai.with_tags(tags))
+}
+
+/// The `membership` column of the `User` row `user_id`. A user without a row is held to the `Unpaid` budgets
+async fn membership(db: &DatabaseConnection, user_id: &str) -> rust_openai::Result<Membership> {
+    let statement = Statement::from_sql_and_values(db.get_database_backend(), "SELECT membership FROM User WHERE id = ?", [user_id.into()]);
+    let membership = match db.query_one(statement).await? {
+        Some(row) => Membership::from_tag(&row.try_get::<String>("", "membership")?),
+        None => Membership::Unpaid,
+    };
+    Ok(membership)
+}